@@ -0,0 +1,234 @@
+//! A small cellular automaton engine, shared by days where every cell's next
+//! state comes from its neighbors' current ones.
+
+/// Which cells a rule looks at, as (dx, dy) offsets from the cell itself.
+///
+/// Rules see neighbors in the order given here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighborhood(pub &'static [(isize, isize)]);
+
+impl Neighborhood {
+    /// Up, left, right, and down
+    pub const VON_NEUMANN: Neighborhood = Neighborhood(&[(0, -1), (-1, 0), (1, 0), (0, 1)]);
+
+    /// All eight surrounding cells, in reading order
+    pub const MOORE: Neighborhood = Neighborhood(&[
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ]);
+
+    /// The 3x3 square centered on the cell (itself included), in reading order
+    pub const SQUARE: Neighborhood = Neighborhood(&[
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (0, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ]);
+
+    /// How far the neighborhood reaches in any direction
+    pub fn reach(&self) -> usize {
+        self.0
+            .iter()
+            .map(|(dx, dy)| std::cmp::max(dx.unsigned_abs(), dy.unsigned_abs()))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// What lies past the edge of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary<T> {
+    /// Every cell outside the grid is this value, forever.
+    Fixed(T),
+    /// The grid wraps around like a torus.
+    Wrap,
+    /// The grid sits in an infinite plane of this value.
+    /// Each step grows the grid by the neighborhood's reach (since those cells
+    /// can now differ from the rest of the plane), then runs the rule over the
+    /// plane itself to find its next value.
+    Infinite(T),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Automaton<T> {
+    cells: Vec<T>,
+    width: usize,
+    height: usize,
+    boundary: Boundary<T>,
+}
+
+impl<T: Copy> Automaton<T> {
+    pub fn new(cells: Vec<T>, width: usize, boundary: Boundary<T>) -> Self {
+        assert_eq!(cells.len() % width, 0);
+        let height = cells.len() / width;
+        Self {
+            cells,
+            width,
+            height,
+            boundary,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cells(&self) -> &[T] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+
+    pub fn boundary(&self) -> &Boundary<T> {
+        &self.boundary
+    }
+
+    /// Get a cell - out-of-bounds values are furnished by the boundary
+    pub fn get(&self, x: isize, y: isize) -> T {
+        let (w, h) = (self.width as isize, self.height as isize);
+        if x >= 0 && x < w && y >= 0 && y < h {
+            return self.cells[x as usize + y as usize * self.width];
+        }
+
+        match self.boundary {
+            Boundary::Fixed(v) | Boundary::Infinite(v) => v,
+            Boundary::Wrap => {
+                let x = x.rem_euclid(w) as usize;
+                let y = y.rem_euclid(h) as usize;
+                self.cells[x + y * self.width]
+            }
+        }
+    }
+
+    /// Gathers the cell's neighborhood into `buf`, in the neighborhood's order.
+    pub fn neighborhood_into(&self, x: isize, y: isize, n: Neighborhood, buf: &mut Vec<T>) {
+        buf.clear();
+        buf.extend(n.0.iter().map(|(dx, dy)| self.get(x + dx, y + dy)));
+    }
+
+    /// Gathers the indexes of the cell's neighbors that are actually
+    /// in the grid (wrapping around if the boundary does),
+    /// in the neighborhood's order.
+    pub fn neighbor_indices_into(&self, i: usize, n: Neighborhood, buf: &mut Vec<usize>) {
+        let (w, h) = (self.width as isize, self.height as isize);
+        let x = (i % self.width) as isize;
+        let y = (i / self.width) as isize;
+
+        buf.clear();
+        for (dx, dy) in n.0 {
+            let (mut nx, mut ny) = (x + dx, y + dy);
+            if let Boundary::Wrap = self.boundary {
+                nx = nx.rem_euclid(w);
+                ny = ny.rem_euclid(h);
+            }
+            if nx >= 0 && nx < w && ny >= 0 && ny < h {
+                buf.push(nx as usize + ny as usize * self.width);
+            }
+        }
+    }
+
+    /// Updates every cell without looking at its neighbors.
+    pub fn map<F: FnMut(T) -> T>(&mut self, mut f: F) {
+        for cell in &mut self.cells {
+            *cell = f(*cell);
+        }
+    }
+
+    /// Runs one generation: every cell's next state is
+    /// `rule(cell, neighbors)`, all computed from the current states.
+    pub fn step<F: FnMut(T, &[T]) -> T>(&self, n: Neighborhood, mut rule: F) -> Self {
+        // An infinite grid grows to cover cells the rule can now reach.
+        let (margin, boundary) = match self.boundary {
+            Boundary::Infinite(plane) => {
+                let around = vec![plane; n.0.len()];
+                (n.reach(), Boundary::Infinite(rule(plane, &around)))
+            }
+            other => (0, other),
+        };
+
+        let width = self.width + 2 * margin;
+        let height = self.height + 2 * margin;
+        let mut cells = Vec::with_capacity(width * height);
+        let mut buf = Vec::with_capacity(n.0.len());
+
+        let margin = margin as isize;
+        for y in -margin..(self.height as isize + margin) {
+            for x in -margin..(self.width as isize + margin) {
+                self.neighborhood_into(x, y, n, &mut buf);
+                cells.push(rule(self.get(x, y), &buf));
+            }
+        }
+
+        Self {
+            cells,
+            width,
+            height,
+            boundary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Conway's Game of Life
+    fn life(alive: bool, neighbors: &[bool]) -> bool {
+        let count = neighbors.iter().filter(|n| **n).count();
+        count == 3 || (alive && count == 2)
+    }
+
+    #[test]
+    fn blinker() {
+        let grid = Automaton::new(
+            vec![false, false, false, true, true, true, false, false, false],
+            3,
+            Boundary::Fixed(false),
+        );
+        let next = grid.step(Neighborhood::MOORE, life);
+        assert_eq!(
+            next.cells(),
+            [false, true, false, false, true, false, false, true, false]
+        );
+        assert!(next.step(Neighborhood::MOORE, life) == grid);
+    }
+
+    #[test]
+    fn boundaries() {
+        let grid = Automaton::new(vec![1, 2, 3, 4], 2, Boundary::Wrap);
+        assert_eq!(grid.get(-1, 0), 2);
+        assert_eq!(grid.get(0, -1), 3);
+        assert_eq!(grid.get(5, 5), 4);
+
+        let mut neighbors = Vec::new();
+        grid.neighbor_indices_into(0, Neighborhood::VON_NEUMANN, &mut neighbors);
+        assert_eq!(neighbors, [2, 1, 1, 2]);
+        let grid = Automaton::new(vec![1, 2, 3, 4], 2, Boundary::Fixed(0));
+        grid.neighbor_indices_into(0, Neighborhood::VON_NEUMANN, &mut neighbors);
+        assert_eq!(neighbors, [1, 2]);
+
+        // A plane of ones, where every cell becomes the sum of its neighbors
+        let grid = Automaton::new(vec![0], 1, Boundary::Infinite(1));
+        let next = grid.step(Neighborhood::VON_NEUMANN, |_, n| n.iter().sum());
+        assert_eq!((next.width(), next.height()), (3, 3));
+        assert_eq!(next.cells(), [4, 3, 4, 3, 4, 3, 4, 3, 4]);
+        assert_eq!(*next.boundary(), Boundary::Infinite(4));
+    }
+}
@@ -1,80 +1,223 @@
 use aoc_runner_derive::aoc;
 
-use std::fmt::{Debug, Error, Formatter};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::io::{self, Write};
 
-use fixedbitset::FixedBitSet;
+use rustc_hash::FxHashMap;
+
+use crate::automaton::{Automaton, Boundary, Neighborhood};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Octopus {
+    energy: i8,
+    /// Has this octopus already lit up its neighbors this step?
+    flashed: bool,
+}
+
+impl Octopus {
+    /// Is this octopus flashing for the first time this step?
+    fn flashing(&self) -> bool {
+        self.energy > 9 && !self.flashed
+    }
+}
 
 pub struct Octopi {
-    cells: Vec<i8>,
-    width: usize,
-    height: usize,
+    grid: Automaton<Octopus>,
 }
 
 impl Octopi {
     fn increment_all(&mut self) {
-        for cell in &mut self.cells {
-            *cell += 1;
-        }
+        self.grid.map(|o| Octopus {
+            energy: o.energy + 1,
+            ..o
+        });
     }
 
-    fn propagate_flashes(&mut self) {
-        let mut to_prop = FixedBitSet::with_capacity(self.cells.len());
+    /// Flashes light up all the neighbors, which can make them flash,
+    /// which lights up their neighbors...
+    ///
+    /// Octopi go on a worklist the moment they have enough energy to flash,
+    /// and get marked so they're never queued twice. Each octopus flashes
+    /// at most once a step, and a huge cascade is just a long queue
+    /// instead of a deep stack or a pile of full-grid passes.
+    ///
+    /// `on_flash` gets the index of each flashing octopus, in cascade order.
+    fn propagate_flashes<F: FnMut(usize)>(&mut self, mut on_flash: F) {
+        let mut to_flash = VecDeque::new();
 
-        for (idx, cell) in self.cells.iter().enumerate() {
-            if *cell > 9 {
-                to_prop.insert(idx);
+        for (i, o) in self.grid.cells_mut().iter_mut().enumerate() {
+            if o.flashing() {
+                o.flashed = true;
+                to_flash.push_back(i);
             }
         }
 
-        for idx in to_prop.ones() {
-            let y = idx / self.width;
-            let x = idx % self.width;
-            self.propagate_flash(x, y);
+        let mut neighbors = Vec::with_capacity(Neighborhood::MOORE.0.len());
+        while let Some(i) = to_flash.pop_front() {
+            on_flash(i);
+
+            self.grid
+                .neighbor_indices_into(i, Neighborhood::MOORE, &mut neighbors);
+            for &n in &neighbors {
+                let o = &mut self.grid.cells_mut()[n];
+                o.energy = o.energy.saturating_add(1);
+                if o.flashing() {
+                    o.flashed = true;
+                    to_flash.push_back(n);
+                }
+            }
         }
     }
 
-    fn propagate_flash(&mut self, x: usize, y: usize) {
-        self.illuminate(x - 1, y - 1);
-        self.illuminate(x, y - 1);
-        self.illuminate(x + 1, y - 1);
-        self.illuminate(x - 1, y);
-        self.illuminate(x, y);
-        self.illuminate(x + 1, y);
-        self.illuminate(x - 1, y + 1);
-        self.illuminate(x, y + 1);
-        self.illuminate(x + 1, y + 1);
+    fn reset_flashes(&mut self) -> i64 {
+        let mut flashes = 0;
+
+        self.grid.map(|o| {
+            if o.energy > 9 {
+                flashes += 1;
+                Octopus {
+                    energy: 0,
+                    flashed: false,
+                }
+            } else {
+                o
+            }
+        });
+
+        flashes
+    }
+
+    /// Runs a whole step, returning how many octopi flashed.
+    fn step(&mut self) -> i64 {
+        self.increment_all();
+        self.propagate_flashes(|_| {});
+        self.reset_flashes()
     }
 
-    fn illuminate(&mut self, x: usize, y: usize) {
-        if x >= self.width || y >= self.height {
-            return;
+    /// Runs a whole step, recording who flashed and when.
+    pub fn step_logged(&mut self) -> StepLog {
+        let width = self.grid.width();
+        let mut flashes = Vec::new();
+
+        self.increment_all();
+        self.propagate_flashes(|i| flashes.push((i % width, i / width)));
+        self.reset_flashes();
+
+        let total_energy = self.grid.cells().iter().map(|o| o.energy as i64).sum();
+        StepLog {
+            flashes,
+            total_energy,
         }
-        let cell = &mut self.cells[x + y * self.width];
-        *cell += 1;
-        if *cell == 10 {
-            self.propagate_flash(x, y);
+    }
+
+    /// Writes the current energies as a plain (ASCII) PGM image,
+    /// with 0 as black and 9 as white.
+    pub fn write_pgm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "P2")?;
+        writeln!(w, "{} {}", self.grid.width(), self.grid.height())?;
+        writeln!(w, "9")?;
+        for line in self.grid.cells().chunks(self.grid.width()) {
+            let row: Vec<String> = line.iter().map(|o| o.energy.to_string()).collect();
+            writeln!(w, "{}", row.join(" "))?;
         }
+        Ok(())
     }
 
-    fn reset_flashes(&mut self) -> i64 {
-        let mut flashes = 0;
+    /// Shows the grid with terminal colors:
+    /// octopi that just flashed glow bold white, and the rest get brighter
+    /// as they charge up.
+    pub fn ansi(&self) -> AnsiFrame<'_> {
+        AnsiFrame { octos: self }
+    }
 
-        for cell in &mut self.cells {
-            if *cell > 9 {
-                flashes += 1;
-                *cell = 0;
+    fn all_dark(&self) -> bool {
+        self.grid.cells().iter().all(|o| o.energy == 0)
+    }
+
+    /// Everything there is to know about the grid's state between steps
+    fn energies(&self) -> Vec<i8> {
+        self.grid.cells().iter().map(|o| o.energy).collect()
+    }
+}
+
+/// What happened in a single step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepLog {
+    /// Every octopus that flashed, as (x, y), in the order the cascade
+    /// reached them
+    pub flashes: Vec<(usize, usize)>,
+    /// The sum of every octopus' energy at the end of the step
+    pub total_energy: i64,
+}
+
+pub struct AnsiFrame<'a> {
+    octos: &'a Octopi,
+}
+
+impl Display for AnsiFrame<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let grid = &self.octos.grid;
+        for line in grid.cells().chunks(grid.width()) {
+            for o in line {
+                if o.energy == 0 {
+                    write!(f, "\x1b[1;97m0")?;
+                } else {
+                    // Dark gray to light gray from the 256-color palette
+                    write!(f, "\x1b[0;38;5;{}m{}", 234 + o.energy as u8 * 2, o.energy)?;
+                }
             }
+            writeln!(f, "\x1b[0m")?;
+        }
+        Ok(())
+    }
+}
+
+/// When (if ever) a grid of octopi all flash together,
+/// and the loop it eventually falls into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncAnalysis {
+    /// The first step where every octopus flashes at once, if any
+    pub first_sync: Option<usize>,
+    /// The number of steps before the grid enters its cycle
+    pub cycle_start: usize,
+    /// How many steps it takes to come back around
+    pub period: usize,
+}
+
+/// Steps the grid until it repeats a state.
+///
+/// Since the octopi are deterministic, a repeated state means we've seen
+/// every state we ever will. If they haven't all flashed together by then,
+/// they never will.
+///
+/// Returns `None` if it takes more than `step_limit` steps to find a cycle.
+pub fn analyze_sync(mut octos: Octopi, step_limit: usize) -> Option<SyncAnalysis> {
+    let mut seen: FxHashMap<Vec<i8>, usize> = FxHashMap::default();
+    let mut first_sync = None;
+
+    for steps in 0..=step_limit {
+        if let Some(start) = seen.insert(octos.energies(), steps) {
+            return Some(SyncAnalysis {
+                first_sync,
+                cycle_start: start,
+                period: steps - start,
+            });
         }
 
-        flashes
+        octos.step();
+        if first_sync.is_none() && octos.all_dark() {
+            first_sync = Some(steps + 1);
+        }
     }
+    None
 }
 
 impl Debug for Octopi {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        for line in self.cells.chunks(self.width) {
+        for line in self.grid.cells().chunks(self.grid.width()) {
             for val in line {
-                write!(f, "{}", val)?;
+                write!(f, "{}", val.energy)?;
             }
             writeln!(f)?;
         }
@@ -83,24 +226,29 @@ impl Debug for Octopi {
 }
 
 pub fn parse_octos(input: &str) -> Octopi {
-    let mut cells: Vec<i8> = Vec::new();
+    let mut cells: Vec<Octopus> = Vec::new();
     let mut width: usize = 0;
-    let mut height: usize = 0;
 
     for line in input.lines() {
         let bytes = line.as_bytes();
         width = bytes.len();
-        height += 1;
 
         for b in bytes {
-            cells.push((b - b'0') as i8);
+            cells.push(Octopus {
+                energy: (b - b'0') as i8,
+                flashed: false,
+            });
         }
     }
 
+    // There's nobody past the edges to flash at us.
+    let nobody = Octopus {
+        energy: 0,
+        flashed: true,
+    };
+
     Octopi {
-        cells,
-        width,
-        height,
+        grid: Automaton::new(cells, width, Boundary::Fixed(nobody)),
     }
 }
 
@@ -110,25 +258,156 @@ pub fn part1(input: &str) -> i64 {
     let mut flashes = 0;
 
     for _i in 0..100 {
-        octos.increment_all();
-        octos.propagate_flashes();
-        flashes += octos.reset_flashes();
+        flashes += octos.step();
     }
 
     flashes
 }
 
 #[aoc(day11, part2)]
-pub fn part2(input: &str) -> i64 {
-    let mut octos = parse_octos(input);
-    let mut steps = 0;
+pub fn part2(input: &str) -> usize {
+    // Plenty for a 10x10 grid.
+    const STEP_LIMIT: usize = 100_000;
+
+    let octos = parse_octos(input);
+    // Like the puzzle's "first step during which all octopuses flash",
+    // a grid that starts out synchronized needs no steps at all.
+    if octos.all_dark() {
+        return 0;
+    }
+
+    let analysis = analyze_sync(octos, STEP_LIMIT).expect("Couldn't find a cycle");
+    analysis.first_sync.expect("Octopi never flash together")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+5483143223
+2745854711
+5264556173
+6141336146
+6357385478
+4167524645
+2176841721
+6882881134
+4846848554
+5283751526";
+
+    #[test]
+    fn example() {
+        assert_eq!(part1(EXAMPLE), 1656);
+        assert_eq!(part2(EXAMPLE), 195);
+    }
+
+    #[test]
+    fn sync_analysis() {
+        let analysis = analyze_sync(parse_octos(EXAMPLE), 1000).unwrap();
+        assert_eq!(
+            analysis,
+            SyncAnalysis {
+                first_sync: Some(195),
+                cycle_start: 195,
+                // Once they're all at zero, they count up to ten together.
+                period: 10,
+            }
+        );
+
+        // These three never all flash in the same step.
+        let analysis = analyze_sync(parse_octos("002"), 1000).unwrap();
+        assert_eq!(
+            analysis,
+            SyncAnalysis {
+                first_sync: None,
+                cycle_start: 0,
+                period: 9,
+            }
+        );
+
+        assert_eq!(analyze_sync(parse_octos(EXAMPLE), 100), None);
+    }
+
+    #[test]
+    fn already_synced() {
+        assert_eq!(part2("000\n000"), 0);
+
+        // analyze_sync() only counts flashes it steps into.
+        let analysis = analyze_sync(parse_octos("000\n000"), 1000).unwrap();
+        assert_eq!(analysis.first_sync, Some(10));
+        assert_eq!(analysis.cycle_start, 0);
+    }
+
+    #[test]
+    fn small_example() {
+        let mut octos = parse_octos("11111\n19991\n19191\n19991\n11111");
 
-    while octos.cells.iter().any(|c| *c != 0) {
         octos.increment_all();
-        octos.propagate_flashes();
-        octos.reset_flashes();
-        steps += 1;
+        octos.propagate_flashes(|_| {});
+        assert_eq!(octos.reset_flashes(), 9);
+        assert_eq!(
+            format!("{:?}", octos),
+            "34543\n40004\n50005\n40004\n34543\n"
+        );
     }
 
-    steps
+    #[test]
+    fn huge_cascade() {
+        let size = 1000;
+        let nines = vec!["9".repeat(size); size].join("\n");
+        let mut octos = parse_octos(&nines);
+
+        assert_eq!(octos.step(), (size * size) as i64);
+        assert!(octos.all_dark());
+    }
+
+    #[test]
+    fn step_log() {
+        let mut octos = parse_octos("11111\n19991\n19191\n19991\n11111");
+
+        let log = octos.step_logged();
+        // The ring of nines flashes first, which sets off the middle.
+        assert_eq!(
+            log.flashes,
+            [
+                (1, 1),
+                (2, 1),
+                (3, 1),
+                (1, 2),
+                (3, 2),
+                (1, 3),
+                (2, 3),
+                (3, 3),
+                (2, 2)
+            ]
+        );
+        assert_eq!(log.total_energy, 64);
+
+        let mut pgm = Vec::new();
+        octos.write_pgm(&mut pgm).unwrap();
+        assert_eq!(
+            String::from_utf8(pgm).unwrap(),
+            "P2\n5 5\n9\n3 4 5 4 3\n4 0 0 0 4\n5 0 0 0 5\n4 0 0 0 4\n3 4 5 4 3\n"
+        );
+
+        let log = octos.step_logged();
+        assert!(log.flashes.is_empty());
+        assert_eq!(log.total_energy, 64 + 25);
+    }
+
+    #[test]
+    fn ansi_frame() {
+        let mut octos = parse_octos("90\n15");
+        octos.step();
+        assert_eq!(format!("{:?}", octos), "02\n37\n");
+
+        // The one that just flashed is bold white, and the rest are
+        // grays that get lighter the more charged they are.
+        assert_eq!(
+            octos.ansi().to_string(),
+            "\x1b[1;97m0\x1b[0;38;5;238m2\x1b[0m\n\
+             \x1b[0;38;5;240m3\x1b[0;38;5;248m7\x1b[0m\n"
+        );
+    }
 }
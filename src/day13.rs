@@ -1,10 +1,11 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 
 use fixedbitset::FixedBitSet;
+use rustc_hash::FxHashSet;
 
-use std::fmt::{Debug, Error, Formatter};
+use std::fmt::{Debug, Display, Error, Formatter};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Fold {
     X(u16),
     Y(u16),
@@ -26,79 +27,509 @@ impl Transparency {
         self.dots.insert(x + y * self.width)
     }
 
-    fn fold_up(&self, on: usize) -> Self {
-        let height = on;
-        let width = self.width;
-        let dots = FixedBitSet::with_capacity(height * width);
+    fn fold_up(&self, on: usize) -> Result<Self, FoldError> {
+        let fold = Fold::Y(on as u16);
+        let crease = Crease::new(self.height, on).ok_or_else(|| self.off_sheet(fold))?;
 
         let mut folded = Self {
-            dots,
-            width,
-            height,
+            dots: FixedBitSet::with_capacity(self.width * crease.folded_size),
+            width: self.width,
+            height: crease.folded_size,
         };
 
-        // Copy the top half over
-        for top_dot in self.dots.ones() {
-            if top_dot >= folded.dots.len() {
-                break;
-            } else {
-                folded.dots.insert(top_dot);
+        for dot in self.dots.ones() {
+            let (x, y) = (dot % self.width, dot / self.width);
+            let y = crease
+                .place(y)
+                .ok_or(FoldError::DotOnCrease { fold, x, y })?;
+            folded.set(x, y);
+        }
+
+        Ok(folded)
+    }
+
+    fn fold_left(&self, on: usize) -> Result<Self, FoldError> {
+        let fold = Fold::X(on as u16);
+        let crease = Crease::new(self.width, on).ok_or_else(|| self.off_sheet(fold))?;
+
+        let mut folded = Self {
+            dots: FixedBitSet::with_capacity(crease.folded_size * self.height),
+            width: crease.folded_size,
+            height: self.height,
+        };
+
+        for dot in self.dots.ones() {
+            let (x, y) = (dot % self.width, dot / self.width);
+            let x = crease
+                .place(x)
+                .ok_or(FoldError::DotOnCrease { fold, x, y })?;
+            folded.set(x, y);
+        }
+
+        Ok(folded)
+    }
+
+    fn off_sheet(&self, fold: Fold) -> FoldError {
+        FoldError::OffSheet {
+            fold,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Where a fold along one axis sends each coordinate.
+///
+/// The far side flips over onto the near one. If the far side is the longer
+/// of the two, it hangs past the near edge, so the folded sheet grows
+/// on that end and everything on the near side shifts over to make room.
+#[derive(Debug, Copy, Clone)]
+struct Crease {
+    on: usize,
+    folded_size: usize,
+    /// How far the near side moves over
+    shift: usize,
+}
+
+impl Crease {
+    /// Folds a sheet `size` long at `on`, or `None` if that's off the sheet.
+    fn new(size: usize, on: usize) -> Option<Self> {
+        if on >= size {
+            return None;
+        }
+        let folded_size = std::cmp::max(on, size - on - 1);
+        Some(Self {
+            on,
+            folded_size,
+            shift: folded_size - on,
+        })
+    }
+
+    /// Where `v` ends up, or `None` if it's right on the crease.
+    fn place(&self, v: usize) -> Option<usize> {
+        use std::cmp::Ordering::*;
+
+        match v.cmp(&self.on) {
+            Less => Some(v + self.shift),
+            Equal => None,
+            Greater => Some(self.folded_size + self.on - v),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FoldError {
+    /// The crease doesn't cross the sheet.
+    OffSheet {
+        fold: Fold,
+        width: usize,
+        height: usize,
+    },
+    /// A dot sits right on the crease, so it has nowhere to go.
+    DotOnCrease { fold: Fold, x: usize, y: usize },
+}
+
+impl Display for FoldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            FoldError::OffSheet {
+                fold,
+                width,
+                height,
+            } => write!(f, "Can't {} a {}x{} sheet", fold, width, height),
+            FoldError::DotOnCrease { fold, x, y } => {
+                write!(
+                    f,
+                    "Can't {}: there's a dot on the crease at {},{}",
+                    fold, x, y
+                )
             }
         }
+    }
+}
 
-        // Apply the bottom half, mirrored, to the top.
-        let mut mirrored_y = height - 1;
-        for y in (on + 1)..self.height {
-            for x in 0..width {
-                if self.get(x, y) {
-                    folded.set(x, mirrored_y);
-                }
+impl std::error::Error for FoldError {}
+
+impl Display for Fold {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            Fold::X(on) => write!(f, "fold along x={}", on),
+            Fold::Y(on) => write!(f, "fold along y={}", on),
+        }
+    }
+}
+
+/// Checks that every fold in the sequence crosses the sheet
+/// (as it is by the time we get to that fold), without touching any dots.
+///
+/// Returns the size of the fully-folded sheet.
+pub fn validate_folds(
+    width: usize,
+    height: usize,
+    folds: &[Fold],
+) -> Result<(usize, usize), FoldError> {
+    folds
+        .iter()
+        .try_fold((width, height), |(width, height), &fold| {
+            let crease = match fold {
+                Fold::X(on) => Crease::new(width, on as usize),
+                Fold::Y(on) => Crease::new(height, on as usize),
+            };
+            match (fold, crease) {
+                (Fold::X(_), Some(c)) => Ok((c.folded_size, height)),
+                (Fold::Y(_), Some(c)) => Ok((width, c.folded_size)),
+                (_, None) => Err(FoldError::OffSheet {
+                    fold,
+                    width,
+                    height,
+                }),
             }
-            mirrored_y -= 1;
+        })
+}
+
+/// A sheet of transparent paper with dots on it
+pub trait Sheet: Sized {
+    fn from_points(points: &[(u16, u16)]) -> Self;
+
+    /// Width and height
+    fn dimensions(&self) -> (usize, usize);
+
+    fn fold(&self, f: Fold) -> Result<Self, FoldError>;
+
+    fn dot_count(&self) -> usize;
+
+    /// Makes every fold in turn, checking up front that they all fit the sheet.
+    fn fold_all(self, folds: &[Fold]) -> Result<Self, FoldError> {
+        let (width, height) = self.dimensions();
+        validate_folds(width, height, folds)?;
+        folds.iter().try_fold(self, |t, f| t.fold(*f))
+    }
+}
+
+impl Sheet for Transparency {
+    fn from_points(points: &[(u16, u16)]) -> Self {
+        points_to_transparency(points)
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn fold(&self, f: Fold) -> Result<Self, FoldError> {
+        match f {
+            Fold::X(on) => self.fold_left(on as usize),
+            Fold::Y(on) => self.fold_up(on as usize),
         }
+    }
 
-        folded
+    fn dot_count(&self) -> usize {
+        self.dots.ones().count()
     }
+}
 
-    fn fold_left(&self, on: usize) -> Self {
-        let height = self.height;
-        let width = on;
-        let dots = FixedBitSet::with_capacity(height * width);
+/// A transparency that only stores its dots,
+/// for when there are few of them spread over a huge sheet.
+///
+/// Folding reflects each dot across the crease,
+/// and dots landing on each other merge in the set.
+#[derive(Default, Clone)]
+pub struct SparseTransparency {
+    dots: FxHashSet<(u16, u16)>,
+    // One past the largest coordinate, so these don't fit in a u16.
+    width: usize,
+    height: usize,
+}
 
-        let mut folded = Self {
+impl Sheet for SparseTransparency {
+    fn from_points(points: &[(u16, u16)]) -> Self {
+        let dots: FxHashSet<_> = points.iter().copied().collect();
+        let width = dots.iter().map(|(x, _)| *x as usize + 1).max().unwrap_or(0);
+        let height = dots.iter().map(|(_, y)| *y as usize + 1).max().unwrap_or(0);
+        Self {
             dots,
             width,
             height,
+        }
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn fold(&self, f: Fold) -> Result<Self, FoldError> {
+        let (width, height) = self.dimensions();
+        let crease = match f {
+            Fold::X(on) => Crease::new(width, on as usize),
+            Fold::Y(on) => Crease::new(height, on as usize),
+        }
+        .ok_or(FoldError::OffSheet {
+            fold: f,
+            width,
+            height,
+        })?;
+
+        let mut dots = FxHashSet::default();
+        dots.reserve(self.dots.len());
+        for &(x, y) in &self.dots {
+            let (x, y) = (x as usize, y as usize);
+            let on_crease = FoldError::DotOnCrease { fold: f, x, y };
+            // Folded sheets are never bigger than the original, so these fit.
+            let folded = match f {
+                Fold::X(_) => (crease.place(x).ok_or(on_crease)?, y),
+                Fold::Y(_) => (x, crease.place(y).ok_or(on_crease)?),
+            };
+            dots.insert((folded.0 as u16, folded.1 as u16));
+        }
+
+        let (width, height) = match f {
+            Fold::X(_) => (crease.folded_size, height),
+            Fold::Y(_) => (width, crease.folded_size),
         };
 
-        // Copy the left half over
-        for y in 0..height {
-            for x in 0..width {
-                if self.get(x, y) {
-                    folded.set(x, y);
-                }
-            }
+        Ok(Self {
+            dots,
+            width,
+            height,
+        })
+    }
+
+    fn dot_count(&self) -> usize {
+        self.dots.len()
+    }
+}
+
+impl From<&SparseTransparency> for Transparency {
+    fn from(sparse: &SparseTransparency) -> Self {
+        let (width, height) = sparse.dimensions();
+
+        let mut dense = Transparency {
+            dots: FixedBitSet::with_capacity(width * height),
+            width,
+            height,
+        };
+        for (x, y) in &sparse.dots {
+            dense.set(*x as usize, *y as usize);
+        }
+        dense
+    }
+}
+
+/// The block letters folded transparencies spell out are 4 dots wide
+/// and 6 tall, with a blank column between each.
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+const GLYPH_PITCH: usize = GLYPH_WIDTH + 1;
+
+/// Each known letter, top row to bottom
+#[rustfmt::skip]
+const FONT: [(char, &str); 17] = [
+    ('A', concat!(
+        ".##.",
+        "#..#",
+        "#..#",
+        "####",
+        "#..#",
+        "#..#",
+    )),
+    ('B', concat!(
+        "###.",
+        "#..#",
+        "###.",
+        "#..#",
+        "#..#",
+        "###.",
+    )),
+    ('C', concat!(
+        ".##.",
+        "#..#",
+        "#...",
+        "#...",
+        "#..#",
+        ".##.",
+    )),
+    ('E', concat!(
+        "####",
+        "#...",
+        "###.",
+        "#...",
+        "#...",
+        "####",
+    )),
+    ('F', concat!(
+        "####",
+        "#...",
+        "###.",
+        "#...",
+        "#...",
+        "#...",
+    )),
+    ('G', concat!(
+        ".##.",
+        "#..#",
+        "#...",
+        "#.##",
+        "#..#",
+        ".###",
+    )),
+    ('H', concat!(
+        "#..#",
+        "#..#",
+        "####",
+        "#..#",
+        "#..#",
+        "#..#",
+    )),
+    ('I', concat!(
+        ".###",
+        "..#.",
+        "..#.",
+        "..#.",
+        "..#.",
+        ".###",
+    )),
+    ('J', concat!(
+        "..##",
+        "...#",
+        "...#",
+        "...#",
+        "#..#",
+        ".##.",
+    )),
+    ('K', concat!(
+        "#..#",
+        "#.#.",
+        "##..",
+        "#.#.",
+        "#.#.",
+        "#..#",
+    )),
+    ('L', concat!(
+        "#...",
+        "#...",
+        "#...",
+        "#...",
+        "#...",
+        "####",
+    )),
+    ('O', concat!(
+        ".##.",
+        "#..#",
+        "#..#",
+        "#..#",
+        "#..#",
+        ".##.",
+    )),
+    ('P', concat!(
+        "###.",
+        "#..#",
+        "#..#",
+        "###.",
+        "#...",
+        "#...",
+    )),
+    ('R', concat!(
+        "###.",
+        "#..#",
+        "#..#",
+        "###.",
+        "#.#.",
+        "#..#",
+    )),
+    ('S', concat!(
+        ".###",
+        "#...",
+        "#...",
+        ".##.",
+        "...#",
+        "###.",
+    )),
+    ('U', concat!(
+        "#..#",
+        "#..#",
+        "#..#",
+        "#..#",
+        "#..#",
+        ".##.",
+    )),
+    ('Z', concat!(
+        "####",
+        "...#",
+        "..#.",
+        ".#..",
+        "#...",
+        "####",
+    )),
+];
+
+/// A glyph we couldn't match to any letter in the font
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownGlyph {
+    /// Which glyph this was, counting from the left
+    pub index: usize,
+    /// The glyph itself, as rows of # and .
+    pub art: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcrError {
+    pub unknown: Vec<UnknownGlyph>,
+}
+
+impl std::fmt::Display for OcrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        for glyph in &self.unknown {
+            writeln!(f, "Couldn't read glyph {}:", glyph.index)?;
+            writeln!(f, "{}", glyph.art)?;
         }
+        Ok(())
+    }
+}
 
-        // Apply the right half, mirrored, to the left
-        for y in 0..height {
-            let mut mirrored_x = width - 1;
-            for x in (on + 1)..self.width {
-                if self.get(x, y) {
-                    folded.set(mirrored_x, y);
+impl std::error::Error for OcrError {}
+
+impl Transparency {
+    /// Reads the 4x6 block letters a fully-folded transparency spells.
+    pub fn read_letters(&self) -> Result<String, OcrError> {
+        let num_glyphs = (self.width + 1) / GLYPH_PITCH;
+
+        let mut letters = String::with_capacity(num_glyphs);
+        let mut unknown = Vec::new();
+
+        for index in 0..num_glyphs {
+            let dots = self.glyph_dots(index);
+            match FONT.iter().find(|(_, font_dots)| *font_dots == dots) {
+                Some((c, _)) => letters.push(*c),
+                None => {
+                    let art = dots
+                        .as_bytes()
+                        .chunks(GLYPH_WIDTH)
+                        .map(|row| std::str::from_utf8(row).unwrap())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    unknown.push(UnknownGlyph { index, art });
                 }
-                mirrored_x -= 1;
             }
         }
 
-        folded
+        if unknown.is_empty() {
+            Ok(letters)
+        } else {
+            Err(OcrError { unknown })
+        }
     }
 
-    fn fold(&self, f: Fold) -> Self {
-        match f {
-            Fold::X(on) => self.fold_left(on as usize),
-            Fold::Y(on) => self.fold_up(on as usize),
+    /// The dots in the given glyph, row by row, as # and .
+    fn glyph_dots(&self, index: usize) -> String {
+        let left = index * GLYPH_PITCH;
+        let mut dots = String::with_capacity(GLYPH_WIDTH * GLYPH_HEIGHT);
+
+        for y in 0..GLYPH_HEIGHT {
+            for x in left..(left + GLYPH_WIDTH) {
+                let dot = x < self.width && y < self.height && self.get(x, y);
+                dots.push(if dot { '#' } else { '.' });
+            }
         }
+        dots
     }
 }
 
@@ -181,21 +612,237 @@ fn parse_folds(lines: std::str::Lines<'_>) -> Vec<Fold> {
 }
 
 #[aoc_generator(day13)]
-pub fn parse(input: &str) -> (Transparency, Vec<Fold>) {
+pub fn parse(input: &str) -> (Vec<(u16, u16)>, Vec<Fold>) {
     let (points, remaining_lines) = parse_points(input);
-    let transparency = points_to_transparency(&points);
     let folds = parse_folds(remaining_lines);
 
-    (transparency, folds)
+    (points, folds)
+}
+
+fn first_fold_dots<S: Sheet>(points: &[(u16, u16)], folds: &[Fold]) -> usize {
+    S::from_points(points)
+        .fold(folds[0])
+        .unwrap_or_else(|e| panic!("{}", e))
+        .dot_count()
+}
+
+fn fold_points<S: Sheet>(points: &[(u16, u16)], folds: &[Fold]) -> S {
+    S::from_points(points)
+        .fold_all(folds)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn read_folded(folded_message: &Transparency) -> String {
+    folded_message
+        .read_letters()
+        .unwrap_or_else(|e| panic!("{}\n{:?}", e, folded_message))
 }
 
 #[aoc(day13, part1)]
-pub fn part1((transparency, folds): &(Transparency, Vec<Fold>)) -> usize {
-    transparency.fold(folds[0]).dots.ones().count()
+pub fn part1((points, folds): &(Vec<(u16, u16)>, Vec<Fold>)) -> usize {
+    first_fold_dots::<Transparency>(points, folds)
+}
+
+#[aoc(day13, part1, Sparse)]
+pub fn part1_sparse((points, folds): &(Vec<(u16, u16)>, Vec<Fold>)) -> usize {
+    first_fold_dots::<SparseTransparency>(points, folds)
 }
 
 #[aoc(day13, part2)]
-pub fn part2((transparency, folds): &(Transparency, Vec<Fold>)) -> String {
-    let folded_message = folds.iter().fold(transparency.clone(), |t, f| t.fold(*f));
-    format!("\n{:?}", folded_message)
+pub fn part2((points, folds): &(Vec<(u16, u16)>, Vec<Fold>)) -> String {
+    read_folded(&fold_points::<Transparency>(points, folds))
+}
+
+#[aoc(day13, part2, Sparse)]
+pub fn part2_sparse((points, folds): &(Vec<(u16, u16)>, Vec<Fold>)) -> String {
+    let folded: SparseTransparency = fold_points(points, folds);
+    read_folded(&Transparency::from(&folded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_art(art: &str) -> Transparency {
+        let mut points = Vec::new();
+        for (y, line) in art.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                if c == '#' {
+                    points.push((x as u16, y as u16));
+                }
+            }
+        }
+        points_to_transparency(&points)
+    }
+
+    #[test]
+    fn ocr() {
+        let message = from_art(
+            "\
+#..#..###...##.####
+#..#...#.....#.#...
+####...#.....#.###.
+#..#...#.....#.#...
+#..#...#..#..#.#...
+#..#..###..##..####",
+        );
+        assert_eq!(message.read_letters(), Ok("HIJE".to_string()));
+
+        let garbled = from_art(
+            "\
+#..#.#..#
+#..#.##.#
+####.#.##
+#..#.#..#
+#..#.#..#
+#..#.#..#",
+        );
+        assert_eq!(
+            garbled.read_letters(),
+            Err(OcrError {
+                unknown: vec![UnknownGlyph {
+                    index: 1,
+                    art: "#..#\n##.#\n#.##\n#..#\n#..#\n#..#".to_string()
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn whole_font() {
+        // Every letter we know, laid out the way a fold prints them
+        let alphabet = from_art(
+            "\
+.##..###...##..####.####..##..#..#..###...##.#..#.#.....##..###..###...###.#..#.####
+#..#.#..#.#..#.#....#....#..#.#..#...#.....#.#.#..#....#..#.#..#.#..#.#....#..#....#
+#..#.###..#....###..###..#....####...#.....#.##...#....#..#.#..#.#..#.#....#..#...#.
+####.#..#.#....#....#....#.##.#..#...#.....#.#.#..#....#..#.###..###...##..#..#..#..
+#..#.#..#.#..#.#....#....#..#.#..#...#..#..#.#.#..#....#..#.#....#.#.....#.#..#.#...
+#..#.###...##..####.#.....###.#..#..###..##..#..#.####..##..#....#..#.###...##..####",
+        );
+        assert_eq!(alphabet.read_letters(), Ok("ABCEFGHIJKLOPRSUZ".to_string()));
+
+        // Nothing in the font that isn't above
+        let letters: String = FONT.iter().map(|(c, _)| *c).collect();
+        assert_eq!(letters, "ABCEFGHIJKLOPRSUZ");
+        for (i, (c, dots)) in FONT.iter().enumerate() {
+            assert_eq!(dots.len(), GLYPH_WIDTH * GLYPH_HEIGHT, "{}", c);
+            assert!(dots.bytes().all(|b| b == b'#' || b == b'.'), "{}", c);
+            assert!(FONT[..i].iter().all(|(_, d)| d != dots), "{}", c);
+        }
+    }
+
+    const EXAMPLE: &str = "\
+6,10
+0,14
+9,10
+0,3
+10,4
+4,11
+6,0
+6,12
+4,1
+0,13
+10,12
+3,4
+3,0
+8,4
+1,10
+2,14
+8,10
+9,0
+
+fold along y=7
+fold along x=5";
+
+    #[test]
+    fn sparse_matches_dense() {
+        let (points, folds) = parse(EXAMPLE);
+        assert_eq!(part1(&(points.clone(), folds.clone())), 17);
+        assert_eq!(part1_sparse(&(points.clone(), folds.clone())), 17);
+
+        let dense: Transparency = fold_points(&points, &folds);
+        let sparse: SparseTransparency = fold_points(&points, &folds);
+        assert_eq!(sparse.dot_count(), 16);
+        assert_eq!(
+            format!("{:?}", Transparency::from(&sparse)),
+            format!("{:?}", dense)
+        );
+        assert_eq!(
+            format!("{:?}", dense),
+            "#####\n#...#\n#...#\n#...#\n#####\n.....\n.....\n"
+        );
+
+        // A couple of dots on a sheet far too big to store densely
+        let far = [(0, 0), (60_000, 60_000)];
+        let folded = SparseTransparency::from_points(&far)
+            .fold(Fold::X(30_000))
+            .unwrap()
+            .fold(Fold::Y(30_000))
+            .unwrap();
+        assert_eq!(folded.dot_count(), 1);
+        assert_eq!((folded.width, folded.height), (30_000, 30_000));
+
+        // All the way out to the last coordinate there is
+        let edge = SparseTransparency::from_points(&[(0, 0), (u16::MAX, u16::MAX)]);
+        assert_eq!(edge.dimensions(), (65_536, 65_536));
+        let folded = edge.fold_all(&[Fold::X(32_767), Fold::Y(32_767)]).unwrap();
+        assert_eq!(folded.dimensions(), (32_768, 32_768));
+        assert_eq!(folded.dot_count(), 2);
+    }
+
+    fn both_ways(points: &[(u16, u16)], fold: Fold) -> Result<String, FoldError> {
+        let dense = Transparency::from_points(points).fold(fold)?;
+        let sparse = SparseTransparency::from_points(points).fold(fold)?;
+        let dense = format!("{:?}", dense);
+        assert_eq!(format!("{:?}", Transparency::from(&sparse)), dense);
+        Ok(dense)
+    }
+
+    #[test]
+    fn asymmetric_folds() {
+        // The far side is longer, so it hangs past the top
+        // and the sheet grows upward.
+        let points = [(0, 0), (1, 2), (0, 5)];
+        assert_eq!(both_ways(&points, Fold::Y(1)).unwrap(), "#.\n..\n..\n##\n");
+
+        // And the same thing sideways, with the longer side on the left
+        let points = [(0, 0), (2, 1), (3, 0)];
+        assert_eq!(both_ways(&points, Fold::X(1)).unwrap(), "##\n.#\n");
+        assert_eq!(
+            both_ways(&points, Fold::X(2)),
+            Err(FoldError::DotOnCrease {
+                fold: Fold::X(2),
+                x: 2,
+                y: 1
+            })
+        );
+        assert_eq!(
+            both_ways(&points, Fold::X(4)),
+            Err(FoldError::OffSheet {
+                fold: Fold::X(4),
+                width: 4,
+                height: 2
+            })
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(
+            validate_folds(11, 15, &[Fold::Y(7), Fold::X(5)]),
+            Ok((5, 7))
+        );
+        assert_eq!(validate_folds(11, 15, &[Fold::Y(2)]), Ok((11, 12)));
+
+        let err = validate_folds(11, 15, &[Fold::Y(7), Fold::Y(7)]).unwrap_err();
+        assert_eq!(err.to_string(), "Can't fold along y=7 a 11x7 sheet");
+
+        // Nothing gets folded if a later fold is bad.
+        let (points, _) = parse(EXAMPLE);
+        let err = Transparency::from_points(&points)
+            .fold_all(&[Fold::Y(7), Fold::X(5), Fold::X(5)])
+            .unwrap_err();
+        assert!(matches!(err, FoldError::OffSheet { .. }));
+    }
 }
@@ -3,6 +3,8 @@ use aoc_runner_derive::aoc;
 use itertools::Itertools;
 use rustc_hash::FxHashMap;
 
+use crate::matrix::{Arithmetic, Matrix};
+
 type Rules = FxHashMap<[u8; 2], u8>;
 
 pub fn parse(input: &str) -> (&[u8], Rules) {
@@ -23,6 +25,136 @@ pub fn parse(input: &str) -> (&[u8], Rules) {
     (template, rules)
 }
 
+/// Something wrong with the template or rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleProblem {
+    /// The line wasn't a template, a blank, or `AB -> C` where we expected one.
+    Malformed { line: usize },
+    /// Elements are uppercase letters.
+    BadElement { line: usize, found: char },
+    /// The same rule showed up again.
+    Duplicate { line: usize, pair: [u8; 2] },
+    /// A pair already had a rule that inserts something else.
+    Conflict {
+        line: usize,
+        pair: [u8; 2],
+        first: u8,
+        second: u8,
+    },
+    /// Two elements we've seen have no rule for when they're next to each other.
+    Missing { pair: [u8; 2] },
+}
+
+impl std::fmt::Display for RuleProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pair = |p: &[u8; 2]| String::from_utf8_lossy(p).into_owned();
+        match self {
+            RuleProblem::Malformed { line } => write!(f, "line {} is malformed", line),
+            RuleProblem::BadElement { line, found } => {
+                write!(f, "line {}: '{}' isn't an element", line, found)
+            }
+            RuleProblem::Duplicate { line, pair: p } => {
+                write!(f, "line {}: {} has the same rule again", line, pair(p))
+            }
+            RuleProblem::Conflict {
+                line,
+                pair: p,
+                first,
+                second,
+            } => write!(
+                f,
+                "line {}: {} inserts {}, but an earlier rule said {}",
+                line,
+                pair(p),
+                *second as char,
+                *first as char
+            ),
+            RuleProblem::Missing { pair: p } => write!(f, "no rule for {}", pair(p)),
+        }
+    }
+}
+
+impl std::error::Error for RuleProblem {}
+
+/// Checks everything `parse` takes on faith, returning every problem found
+/// (or none, if the input is good).
+pub fn validate(input: &str) -> Vec<RuleProblem> {
+    let mut problems = Vec::new();
+    let mut elements = [false; 26];
+
+    // Numbering lines from one, like an editor
+    let mut lines = input.lines().zip(1..);
+
+    let mut check_elements = |line: usize, es: &[u8], problems: &mut Vec<RuleProblem>| {
+        let mut ok = true;
+        for e in es {
+            if e.is_ascii_uppercase() {
+                elements[(e - b'A') as usize] = true;
+            } else {
+                problems.push(RuleProblem::BadElement {
+                    line,
+                    found: *e as char,
+                });
+                ok = false;
+            }
+        }
+        ok
+    };
+
+    match lines.next() {
+        Some((template, line)) if !template.is_empty() => {
+            check_elements(line, template.as_bytes(), &mut problems);
+        }
+        _ => problems.push(RuleProblem::Malformed { line: 1 }),
+    }
+    match lines.next() {
+        Some(("", _)) => {}
+        _ => problems.push(RuleProblem::Malformed { line: 2 }),
+    }
+
+    let mut rules: FxHashMap<[u8; 2], u8> = FxHashMap::default();
+    for (rule, line) in lines {
+        let (from, to) = match rule.split_once(" -> ") {
+            Some((from, to)) if from.len() == 2 && to.len() == 1 => {
+                (from.as_bytes(), to.as_bytes()[0])
+            }
+            _ => {
+                problems.push(RuleProblem::Malformed { line });
+                continue;
+            }
+        };
+        if !check_elements(line, &[from[0], from[1], to], &mut problems) {
+            continue;
+        }
+
+        let pair = [from[0], from[1]];
+        match rules.get(&pair) {
+            Some(&first) if first == to => problems.push(RuleProblem::Duplicate { line, pair }),
+            Some(&first) => problems.push(RuleProblem::Conflict {
+                line,
+                pair,
+                first,
+                second: to,
+            }),
+            None => {
+                rules.insert(pair, to);
+            }
+        }
+    }
+
+    let seen: Vec<u8> = (b'A'..=b'Z')
+        .filter(|e| elements[(e - b'A') as usize])
+        .collect();
+    for (a, b) in seen.iter().cartesian_product(&seen) {
+        let pair = [*a, *b];
+        if !rules.contains_key(&pair) {
+            problems.push(RuleProblem::Missing { pair });
+        }
+    }
+
+    problems
+}
+
 fn step(before: &[u8], rules: &Rules) -> Vec<u8> {
     let mut after = Vec::with_capacity(before.len());
 
@@ -47,6 +179,44 @@ fn count(vals: &[u8]) -> FxHashMap<u8, usize> {
     counts
 }
 
+/// The first `n` elements of the chain after the given number of steps,
+/// without building the rest of it.
+///
+/// Each pair expands into a binary tree of insertions, so walk those trees
+/// depth-first, left side first, until we have enough.
+pub fn chain_prefix(template: &[u8], rules: &Rules, steps: usize, n: usize) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(n);
+
+    // Pairs left to expand and how many steps they have left,
+    // with the leftmost on top
+    let mut to_expand: Vec<([u8; 2], usize)> = template
+        .windows(2)
+        .rev()
+        .map(|w| ([w[0], w[1]], steps))
+        .collect();
+
+    while prefix.len() < n {
+        let ([a, b], steps_left) = match to_expand.pop() {
+            Some(p) => p,
+            None => {
+                // Only the very last element is left
+                prefix.extend(template.last());
+                break;
+            }
+        };
+        match rules.get(&[a, b]) {
+            Some(&c) if steps_left > 0 => {
+                to_expand.push(([c, b], steps_left - 1));
+                to_expand.push(([a, c], steps_left - 1));
+            }
+            // Each pair owns its left element; its right one is the next pair's.
+            _ => prefix.push(a),
+        }
+    }
+
+    prefix
+}
+
 #[aoc(day14, part1)]
 pub fn part1(input: &str) -> usize {
     let (template, rules) = parse(input);
@@ -129,3 +299,384 @@ pub fn part2(input: &str) -> usize {
 
     most_common - least_common
 }
+
+// Once more, as a linear map on pair counts.
+// Every pair AB with a rule AB -> C turns into AC and CB,
+// and a polymer is just how many of each pair it has
+// (plus its last element, which never moves).
+
+const ELEMENTS: usize = 26;
+const PAIRS: usize = ELEMENTS * ELEMENTS;
+
+fn element_index(e: u8) -> usize {
+    assert!(e.is_ascii_uppercase(), "{} isn't an element", e as char);
+    (e - b'A') as usize
+}
+
+fn pair_index(a: u8, b: u8) -> usize {
+    element_index(a) * ELEMENTS + element_index(b)
+}
+
+/// Where each pair goes in a step, if it splits
+pub struct PairRules {
+    splits: [Option<(usize, usize)>; PAIRS],
+}
+
+impl PairRules {
+    pub fn new(rules: &Rules) -> Self {
+        let mut splits = [None; PAIRS];
+        for (&[a, b], &c) in rules {
+            splits[pair_index(a, b)] = Some((pair_index(a, c), pair_index(c, b)));
+        }
+        Self { splits }
+    }
+
+    /// Every pair we can ever get to from the given ones, in ascending order
+    fn reachable_from<I: IntoIterator<Item = usize>>(&self, pairs: I) -> Vec<usize> {
+        let mut seen = [false; PAIRS];
+        let mut to_visit: Vec<usize> = pairs.into_iter().collect();
+
+        while let Some(p) = to_visit.pop() {
+            if seen[p] {
+                continue;
+            }
+            seen[p] = true;
+            if let Some((l, r)) = self.splits[p] {
+                to_visit.push(l);
+                to_visit.push(r);
+            }
+        }
+
+        (0..PAIRS).filter(|p| seen[*p]).collect()
+    }
+}
+
+/// The counts got too big for a u128.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overflow {
+    /// How many steps in
+    pub step: usize,
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pair counts overflowed on step {}", self.step)
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairPolymer {
+    pairs: [u128; PAIRS],
+    last: u8,
+}
+
+impl PairPolymer {
+    pub fn new(template: &[u8]) -> Self {
+        let mut pairs = [0; PAIRS];
+        for (a, b) in template.iter().tuple_windows() {
+            pairs[pair_index(*a, *b)] += 1;
+        }
+        Self {
+            pairs,
+            last: *template.last().expect("empty template"),
+        }
+    }
+
+    /// Applies the rules once, or `None` if a count overflows.
+    fn step(&self, rules: &PairRules) -> Option<Self> {
+        let mut pairs = [0u128; PAIRS];
+        for (p, &n) in self.pairs.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            match rules.splits[p] {
+                Some((l, r)) => {
+                    pairs[l] = pairs[l].checked_add(n)?;
+                    pairs[r] = pairs[r].checked_add(n)?;
+                }
+                None => pairs[p] = pairs[p].checked_add(n)?,
+            }
+        }
+        Some(Self {
+            pairs,
+            last: self.last,
+        })
+    }
+
+    pub fn steps(self, rules: &PairRules, steps: usize) -> Result<Self, Overflow> {
+        (1..=steps).try_fold(self, |p, step| p.step(rules).ok_or(Overflow { step }))
+    }
+
+    /// How many of each element there are, indexed from A.
+    ///
+    /// Every element starts exactly one pair, except the last.
+    /// Returns `None` if the total doesn't fit.
+    pub fn element_counts(&self) -> Option<[u128; ELEMENTS]> {
+        let mut counts = [0u128; ELEMENTS];
+        counts[element_index(self.last)] = 1;
+        for (p, &n) in self.pairs.iter().enumerate() {
+            let first = p / ELEMENTS;
+            counts[first] = counts[first].checked_add(n)?;
+        }
+        // The polymer's length has to fit too.
+        counts
+            .iter()
+            .try_fold(0u128, |acc, c| acc.checked_add(*c))?;
+        Some(counts)
+    }
+
+    /// Jumps `steps` steps ahead by raising the step's matrix to that power,
+    /// returning how many of each element there are then.
+    ///
+    /// Returns `None` if the counts don't fit.
+    pub fn element_counts_after(
+        &self,
+        rules: &PairRules,
+        steps: u64,
+        arith: Arithmetic,
+    ) -> Option<[u128; ELEMENTS]> {
+        // Only pairs we can actually reach get a row and column.
+        // That's usually a small fraction of all 676, and multiplying
+        // matrices is cubic.
+        let present = (0..PAIRS).filter(|p| self.pairs[*p] > 0);
+        let reachable = rules.reachable_from(present);
+        let mut row = [usize::MAX; PAIRS];
+        for (i, p) in reachable.iter().enumerate() {
+            row[*p] = i;
+        }
+
+        let mut m = Matrix::zero(reachable.len());
+        for (i, &p) in reachable.iter().enumerate() {
+            match rules.splits[p] {
+                Some((l, r)) => {
+                    m[(row[l], i)] += 1;
+                    m[(row[r], i)] += 1;
+                }
+                None => m[(i, i)] = 1,
+            }
+        }
+
+        let start: Vec<u128> = reachable.iter().map(|p| self.pairs[*p]).collect();
+        let end = m.pow(steps, arith)?.apply(&start, arith)?;
+
+        let mut counts = [0u128; ELEMENTS];
+        counts[element_index(self.last)] = 1;
+        for (p, n) in reachable.iter().zip(end) {
+            let first = p / ELEMENTS;
+            counts[first] = arith.add(counts[first], n)?;
+        }
+        // The polymer's length has to fit too.
+        arith.sum(counts)?;
+        Some(counts)
+    }
+}
+
+fn matrix_spread(input: &str, steps: usize) -> u128 {
+    let (template, rules) = parse(input);
+
+    let polymer = PairPolymer::new(template)
+        .steps(&PairRules::new(&rules), steps)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let counts = polymer.element_counts().expect("element counts overflowed");
+    let present = counts.iter().filter(|c| **c > 0);
+
+    let most_common = present.clone().max().unwrap();
+    let least_common = present.min().unwrap();
+
+    most_common - least_common
+}
+
+#[aoc(day14, part1, Matrix)]
+pub fn part1_matrix(input: &str) -> u128 {
+    matrix_spread(input, 10)
+}
+
+#[aoc(day14, part2, Matrix)]
+pub fn part2_matrix(input: &str) -> u128 {
+    matrix_spread(input, 40)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    const EXAMPLE: &str = "\
+NNCB
+
+CH -> B
+HH -> N
+CB -> H
+NH -> C
+HB -> C
+HC -> B
+HN -> C
+NN -> C
+BH -> H
+NC -> B
+NB -> B
+BN -> B
+BB -> N
+BC -> B
+CC -> N
+CN -> C";
+
+    #[test]
+    fn example() {
+        assert_eq!(part1(EXAMPLE), 1588);
+        assert_eq!(part2(EXAMPLE), 2188189693529);
+        assert_eq!(part1_matrix(EXAMPLE), 1588);
+        assert_eq!(part2_matrix(EXAMPLE), 2188189693529);
+    }
+
+    #[test]
+    fn matrix_matches_materialized() {
+        let (template, rules) = parse(EXAMPLE);
+        let pair_rules = PairRules::new(&rules);
+
+        let mut chain = template.to_vec();
+        let mut polymer = PairPolymer::new(template);
+        for _ in 0..8 {
+            chain = step(&chain, &rules);
+            polymer = polymer.steps(&pair_rules, 1).unwrap();
+
+            let mut expected = [0u128; ELEMENTS];
+            for (e, n) in count(&chain) {
+                expected[element_index(e)] = n as u128;
+            }
+            assert_eq!(polymer.element_counts().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn thousands_of_steps() {
+        let (template, rules) = parse(EXAMPLE);
+        let pair_rules = PairRules::new(&rules);
+
+        // Length doubles (minus one) each step, so u128s run out
+        // a bit past 120 steps.
+        assert!(PairPolymer::new(template).steps(&pair_rules, 120).is_ok());
+        let err = PairPolymer::new(template)
+            .steps(&pair_rules, 5000)
+            .unwrap_err();
+        assert!(err.step > 120 && err.step < 130);
+
+        // Without rules for it, a chain just sits there.
+        let stuck = PairPolymer::new(b"ZZZZ").steps(&pair_rules, 5000).unwrap();
+        let counts = stuck.element_counts().unwrap();
+        assert_eq!(counts[element_index(b'Z')], 4);
+    }
+
+    #[test]
+    fn jump_ahead() {
+        const P: u64 = 1_000_000_007;
+        let modp = Arithmetic::Modulo(NonZeroU64::new(P).unwrap());
+        let (template, rules) = parse(EXAMPLE);
+        let pair_rules = PairRules::new(&rules);
+        let polymer = PairPolymer::new(template);
+
+        for steps in [0, 1, 10, 40, 100] {
+            let stepped = polymer.clone().steps(&pair_rules, steps).unwrap();
+            let stepped = stepped.element_counts().unwrap();
+            let jumped =
+                polymer.element_counts_after(&pair_rules, steps as u64, Arithmetic::Checked);
+            assert_eq!(jumped, Some(stepped));
+
+            let jumped = polymer.element_counts_after(&pair_rules, steps as u64, modp);
+            assert_eq!(jumped, Some(stepped.map(|c| c % P as u128)));
+        }
+
+        // Every pair in the example has a rule, so the polymer's length
+        // goes from n to 2n - 1 each step: after k steps it's 3 * 2^k + 1.
+        let k = 1_000_000_000_000;
+        let counts = polymer.element_counts_after(&pair_rules, k, modp).unwrap();
+        let mut two = Matrix::zero(1);
+        two[(0, 0)] = 2;
+        let two_to_the_k = two.pow(k, modp).unwrap()[(0, 0)];
+        assert_eq!(
+            modp.sum(counts),
+            modp.add(modp.mul(3, two_to_the_k).unwrap(), 1)
+        );
+        assert_eq!(
+            polymer.element_counts_after(&pair_rules, k, Arithmetic::Checked),
+            None
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(validate(EXAMPLE), []);
+
+        let bad = "\
+NNcB
+
+NN -> C
+NN -> C
+NN -> B
+NC -> %
+CN
+BB -> N";
+        let problems = validate(bad);
+        assert_eq!(
+            problems[..5],
+            [
+                RuleProblem::BadElement {
+                    line: 1,
+                    found: 'c'
+                },
+                RuleProblem::Duplicate {
+                    line: 4,
+                    pair: *b"NN"
+                },
+                RuleProblem::Conflict {
+                    line: 5,
+                    pair: *b"NN",
+                    first: b'C',
+                    second: b'B'
+                },
+                RuleProblem::BadElement {
+                    line: 6,
+                    found: '%'
+                },
+                RuleProblem::Malformed { line: 7 },
+            ]
+        );
+        // B, C, and N make nine pairs, and we only have rules for two.
+        assert_eq!(problems.len(), 5 + 7);
+        assert_eq!(problems[5], RuleProblem::Missing { pair: *b"BC" });
+        assert_eq!(problems[4].to_string(), "line 7 is malformed");
+        assert_eq!(
+            problems[2].to_string(),
+            "line 5: NN inserts B, but an earlier rule said C"
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        let (template, rules) = parse(EXAMPLE);
+
+        let mut chain = template.to_vec();
+        for steps in 0..10 {
+            for n in [
+                0,
+                1,
+                2,
+                5,
+                17,
+                chain.len() - 1,
+                chain.len(),
+                chain.len() + 10,
+            ] {
+                let expected = &chain[..n.min(chain.len())];
+                assert_eq!(chain_prefix(template, &rules, steps, n), expected);
+            }
+            chain = step(&chain, &rules);
+        }
+
+        assert_eq!(chain_prefix(template, &rules, 4, 18), b"NBBNBNBBCCNBCNCCNB");
+        // Way past anything we could build
+        assert_eq!(chain_prefix(template, &rules, 1000, 3).len(), 3);
+    }
+}
@@ -1,5 +1,7 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 
+use fixedbitset::FixedBitSet;
+
 use std::fmt::{Debug, Error, Formatter};
 
 #[derive(Default, Clone)]
@@ -9,9 +11,37 @@ pub struct Map {
     height: usize,
 }
 
-type Coordinate = (usize, usize);
+pub type Coordinate = (usize, usize);
+
+/// Something we can find a path through
+pub trait RiskMap {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn risk(&self, c: Coordinate) -> i8;
+
+    fn num_cells(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    /// Shows the map with only the cells on the given path,
+    /// like the puzzle's examples highlight them.
+    fn overlay<'a>(&'a self, path: &'a [Coordinate]) -> PathOverlay<'a, Self>
+    where
+        Self: Sized,
+    {
+        PathOverlay { map: self, path }
+    }
+}
+
+impl RiskMap for Map {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
 
-impl Map {
     fn risk(&self, c: Coordinate) -> i8 {
         let x = c.0;
         let y = c.1;
@@ -21,6 +51,12 @@ impl Map {
     }
 }
 
+impl Map {
+    pub fn tiled(&self, factor: usize) -> TiledMap<'_> {
+        TiledMap { base: self, factor }
+    }
+}
+
 impl Debug for Map {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         for line in self.cells.chunks(self.width) {
@@ -33,6 +69,77 @@ impl Debug for Map {
     }
 }
 
+/// A map repeated `factor` times in each direction,
+/// with each repetition one riskier than the one above or left of it.
+/// Risks above 9 wrap back around to 1.
+///
+/// Risks are computed as they're needed instead of copying the base map
+/// (factor^2) times.
+pub struct TiledMap<'a> {
+    base: &'a Map,
+    factor: usize,
+}
+
+impl RiskMap for TiledMap<'_> {
+    fn width(&self) -> usize {
+        self.base.width * self.factor
+    }
+
+    fn height(&self) -> usize {
+        self.base.height * self.factor
+    }
+
+    fn risk(&self, c: Coordinate) -> i8 {
+        let (tile_x, x) = (c.0 / self.base.width, c.0 % self.base.width);
+        let (tile_y, y) = (c.1 / self.base.height, c.1 % self.base.height);
+        assert!(tile_x < self.factor);
+        assert!(tile_y < self.factor);
+
+        let raised = self.base.risk((x, y)) as usize + tile_x + tile_y;
+        ((raised - 1) % 9 + 1) as i8
+    }
+}
+
+impl Debug for TiledMap<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                write!(f, "{}", self.risk((x, y)))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PathOverlay<'a, M> {
+    map: &'a M,
+    path: &'a [Coordinate],
+}
+
+impl<M: RiskMap> Debug for PathOverlay<'_, M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let width = self.map.width();
+
+        let mut on_path = FixedBitSet::with_capacity(self.map.num_cells());
+        for (x, y) in self.path {
+            on_path.insert(x + y * width);
+        }
+
+        for y in 0..self.map.height() {
+            for x in 0..width {
+                if on_path[x + y * width] {
+                    write!(f, "{}", self.map.risk((x, y)))?;
+                } else {
+                    write!(f, ".")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
 #[aoc_generator(day15)]
 pub fn parse(input: &str) -> Map {
     let mut cells: Vec<i8> = Vec::new();
@@ -45,6 +152,13 @@ pub fn parse(input: &str) -> Map {
         height += 1;
 
         for b in bytes {
+            // Free steps would break dial(), which counts on every step
+            // moving the distance forward.
+            assert!(
+                (b'1'..=b'9').contains(b),
+                "risk must be 1-9, not {}",
+                *b as char
+            );
             cells.push((b - b'0') as i8);
         }
     }
@@ -58,34 +172,241 @@ pub fn parse(input: &str) -> Map {
 
 type Score = i32;
 
-fn a_star(map: &Map) -> Score {
+/// The safest way through a map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Every cell visited, from the top left to the bottom right
+    pub path: Vec<Coordinate>,
+    /// The risk of entering each cell in `path`.
+    /// We start in the first one, so it's free.
+    pub risks: Vec<Score>,
+    pub total: Score,
+}
+
+fn a_star<M: RiskMap>(map: &M) -> Score {
+    a_star_route(map).total
+}
+
+pub fn a_star_route<M: RiskMap>(map: &M) -> Route {
+    a_star_with(map, Heuristic::default()).0
+}
+
+/// How A* estimates the remaining risk to the end.
+///
+/// All of these are admissible (they never overestimate),
+/// so they all find the lowest-risk path. Better estimates just get there
+/// while expanding fewer nodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Heuristic {
+    /// Always zero, which makes A* plain old Dijkstra.
+    Zero,
+    /// Manhattan distance to the end times the lowest risk on the map
+    #[default]
+    Manhattan,
+    /// Every path to the end enters each column right of us and each row
+    /// below us at least once. The sum of each column's (or row's) lowest
+    /// risk is a lower bound, so take whichever of the two is bigger.
+    RowColumnMinimums,
+}
+
+/// How much work a search did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchStats {
+    /// How many nodes had their neighbors examined
+    pub expanded: usize,
+}
+
+/// Whatever a heuristic needs, worked out before we start searching
+struct Estimator {
+    heuristic: Heuristic,
+    end: Coordinate,
+    min_risk: Score,
+    /// The sum of the lowest risk in each column after the given one
+    column_bounds: Vec<Score>,
+    /// The sum of the lowest risk in each row after the given one
+    row_bounds: Vec<Score>,
+}
+
+impl Estimator {
+    fn new<M: RiskMap>(map: &M, heuristic: Heuristic) -> Self {
+        let (width, height) = (map.width(), map.height());
+        let end = (width - 1, height - 1);
+
+        let mut min_risk = Score::MAX;
+        let mut column_bounds = Vec::new();
+        let mut row_bounds = Vec::new();
+
+        match heuristic {
+            Heuristic::Zero => {}
+            Heuristic::Manhattan => {
+                for y in 0..height {
+                    for x in 0..width {
+                        min_risk = std::cmp::min(min_risk, map.risk((x, y)) as Score);
+                    }
+                }
+            }
+            Heuristic::RowColumnMinimums => {
+                let mut column_mins = vec![Score::MAX; width];
+                let mut row_mins = vec![Score::MAX; height];
+                for (y, row_min) in row_mins.iter_mut().enumerate() {
+                    for (x, column_min) in column_mins.iter_mut().enumerate() {
+                        let r = map.risk((x, y)) as Score;
+                        *column_min = std::cmp::min(*column_min, r);
+                        *row_min = std::cmp::min(*row_min, r);
+                    }
+                }
+                column_bounds = suffix_sums_after(&column_mins);
+                row_bounds = suffix_sums_after(&row_mins);
+            }
+        }
+
+        Self {
+            heuristic,
+            end,
+            min_risk,
+            column_bounds,
+            row_bounds,
+        }
+    }
+
+    fn estimate(&self, c: &Coordinate) -> Score {
+        match self.heuristic {
+            Heuristic::Zero => 0,
+            Heuristic::Manhattan => {
+                let steps = (self.end.0 - c.0) + (self.end.1 - c.1);
+                steps as Score * self.min_risk
+            }
+            Heuristic::RowColumnMinimums => {
+                std::cmp::max(self.column_bounds[c.0], self.row_bounds[c.1])
+            }
+        }
+    }
+}
+
+/// For each index, the sum of everything after it
+fn suffix_sums_after(vals: &[Score]) -> Vec<Score> {
+    let mut sums = vec![0; vals.len()];
+    for i in (0..vals.len() - 1).rev() {
+        sums[i] = sums[i + 1] + vals[i + 1];
+    }
+    sums
+}
+
+pub fn a_star_with<M: RiskMap>(map: &M, heuristic: Heuristic) -> (Route, SearchStats) {
     use pathfinding::directed::astar::astar;
 
     const START: Coordinate = (0, 0);
-    let end: Coordinate = (map.width - 1, map.height - 1);
+    let (width, height) = (map.width(), map.height());
+    let end: Coordinate = (width - 1, height - 1);
+
+    let mut stats = SearchStats::default();
 
     let successors = |c: &Coordinate| {
+        stats.expanded += 1;
+
+        // Wrapping around zero puts us out of bounds, which we filter out.
         let neighbors = [
-            (c.0 - 1, c.1),
+            (c.0.wrapping_sub(1), c.1),
             (c.0 + 1, c.1),
-            (c.0, c.1 - 1),
+            (c.0, c.1.wrapping_sub(1)),
             (c.0, c.1 + 1),
         ];
         neighbors
             .into_iter()
-            .filter(|(nx, ny)| *nx < map.width && *ny < map.height)
+            .filter(|(nx, ny)| *nx < width && *ny < height)
             .map(|n| (n, map.risk(n) as Score))
     };
 
-    let heuristic = |c: &Coordinate| -> Score {
-        let dy = c.1 as Score - map.height as Score;
-        let dx = c.0 as Score - map.width as Score;
-        // Assume the average risk is 5.
-        (dx + dy) * 5
-    };
+    let estimator = Estimator::new(map, heuristic);
+
+    let (path, total) = astar(&START, successors, |c| estimator.estimate(c), |c| *c == end)
+        .expect("No path to end");
+
+    let risks = path
+        .iter()
+        .enumerate()
+        .map(|(i, c)| if i == 0 { 0 } else { map.risk(*c) as Score })
+        .collect();
+
+    (Route { path, risks, total }, stats)
+}
+
+/// Dijkstra's algorithm with a bucket queue (Dial's algorithm).
+///
+/// Every step costs 1-9, so everything on the frontier is within 9 of
+/// the closest node. Ten buckets, indexed by distance mod 10, are enough
+/// to pop nodes in order without a heap.
+/// Distances are kept in a Vec parallel to the map's cells instead of
+/// a hash map keyed by coordinates.
+pub fn dial<M: RiskMap>(map: &M) -> Score {
+    const MAX_RISK: usize = 9;
+    const NUM_BUCKETS: usize = MAX_RISK + 1;
+
+    let (width, height) = (map.width(), map.height());
+    let end = map.num_cells() - 1;
+
+    let mut distances = vec![Score::MAX; map.num_cells()];
+    let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); NUM_BUCKETS];
+    let mut queued = 1;
+
+    distances[0] = 0;
+    buckets[0].push(0);
+
+    let mut dist = 0;
+    while queued > 0 {
+        let b = dist as usize % NUM_BUCKETS;
+        let mut bucket = std::mem::take(&mut buckets[b]);
+        queued -= bucket.len();
+
+        for &i in &bucket {
+            // We might have found a shorter path since we queued this one.
+            if distances[i] != dist {
+                continue;
+            }
+            if i == end {
+                return dist;
+            }
+
+            let x = i % width;
+            let y = i / width;
+            let mut visit = |n: usize| {
+                let risk = map.risk((n % width, n / width));
+                assert!(
+                    (1..=MAX_RISK as i8).contains(&risk),
+                    "dial() needs risks of 1-{}, not {}",
+                    MAX_RISK,
+                    risk
+                );
+                let d = dist + risk as Score;
+                if d < distances[n] {
+                    distances[n] = d;
+                    // Steps cost at least one, so this is never the bucket
+                    // we're currently draining.
+                    buckets[d as usize % NUM_BUCKETS].push(n);
+                    queued += 1;
+                }
+            };
+            if x > 0 {
+                visit(i - 1);
+            }
+            if x + 1 < width {
+                visit(i + 1);
+            }
+            if y > 0 {
+                visit(i - width);
+            }
+            if y + 1 < height {
+                visit(i + width);
+            }
+        }
+
+        // Hang onto the allocation
+        bucket.clear();
+        buckets[b] = bucket;
+        dist += 1;
+    }
 
-    let search_result = astar(&START, successors, heuristic, |c| *c == end);
-    search_result.expect("No path to end").1
+    panic!("No path to end");
 }
 
 #[aoc(day15, part1)]
@@ -93,31 +414,162 @@ pub fn part1(input: &Map) -> Score {
     a_star(input)
 }
 
+#[aoc(day15, part1, Dial)]
+pub fn part1_dial(input: &Map) -> Score {
+    dial(input)
+}
+
 #[aoc(day15, part2)]
 pub fn part2(input: &Map) -> Score {
-    let width = input.width * 5;
-    let height = input.height * 5;
-    let mut cells = Vec::with_capacity(input.cells.len() * 5 * 5);
-
-    for dup_y in 0..5 {
-        for y in 0..input.height {
-            for dup_x in 0..5 {
-                let dup = dup_y + dup_x;
-                for x in 0..input.width {
-                    let mut new_val = input.risk((x, y)) + dup;
-                    if new_val > 9 {
-                        new_val -= 9;
-                    }
-                    cells.push(new_val);
-                }
-            }
+    a_star(&input.tiled(5))
+}
+
+#[aoc(day15, part2, Dial)]
+pub fn part2_dial(input: &Map) -> Score {
+    dial(&input.tiled(5))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+1163751742
+1381373672
+2136511328
+3694931569
+7463417111
+1319128137
+1359912421
+3125421639
+1293138521
+2311944581";
+
+    #[test]
+    fn example_route() {
+        let map = parse(EXAMPLE);
+        let route = a_star_route(&map);
+        assert_eq!(route.total, 40);
+        assert_eq!(route.risks.iter().sum::<Score>(), route.total);
+
+        assert_eq!(route.path.first(), Some(&(0, 0)));
+        assert_eq!(route.path.last(), Some(&(9, 9)));
+        // Each step moves to an adjacent cell
+        for (a, b) in route.path.iter().zip(&route.path[1..]) {
+            let dist = (a.0 as isize - b.0 as isize).abs() + (a.1 as isize - b.1 as isize).abs();
+            assert_eq!(dist, 1);
         }
     }
 
-    let embiggened = Map {
-        cells,
-        width,
-        height,
-    };
-    a_star(&embiggened)
+    #[test]
+    fn overlay() {
+        // The puzzle's highlighted path
+        // (there are others with the same risk.)
+        let path = [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (1, 2),
+            (2, 2),
+            (3, 2),
+            (4, 2),
+            (5, 2),
+            (6, 2),
+            (6, 3),
+            (7, 3),
+            (7, 4),
+            (7, 5),
+            (8, 5),
+            (8, 6),
+            (8, 7),
+            (8, 8),
+            (9, 8),
+            (9, 9),
+        ];
+        let expected = "\
+1.........
+1.........
+2136511...
+......15..
+.......1..
+.......13.
+........2.
+........3.
+........21
+.........1
+";
+        let map = parse(EXAMPLE);
+        assert_eq!(format!("{:?}", map.overlay(&path)), expected);
+    }
+
+    #[test]
+    fn example_part2() {
+        assert_eq!(part2(&parse(EXAMPLE)), 315);
+    }
+
+    #[test]
+    fn tiling() {
+        let map = parse("8");
+        let tiled = map.tiled(5);
+        assert_eq!(
+            format!("{:?}", tiled),
+            "89123\n91234\n12345\n23456\n34567\n"
+        );
+
+        // The tile factor isn't limited to 5,
+        // and wraps around more than once.
+        let map = parse("9");
+        let tiled = map.tiled(20);
+        assert_eq!(tiled.risk((0, 0)), 9);
+        assert_eq!(tiled.risk((1, 0)), 1);
+        assert_eq!(tiled.risk((9, 9)), 9);
+        assert_eq!(tiled.risk((19, 19)), 2);
+
+        let map = parse(EXAMPLE);
+        let example = map.tiled(5);
+        assert_eq!((example.width(), example.height()), (50, 50));
+        assert_eq!(example.risk((49, 49)), 9);
+        assert_eq!(example.risk((10, 0)), 2);
+    }
+
+    #[test]
+    fn heuristics() {
+        let map = parse(EXAMPLE);
+        let tiled = map.tiled(5);
+
+        let (_, dijkstra) = a_star_with(&tiled, Heuristic::Zero);
+        for h in [Heuristic::Manhattan, Heuristic::RowColumnMinimums] {
+            assert_eq!(a_star_with(&map, h).0.total, 40);
+
+            let (route, stats) = a_star_with(&tiled, h);
+            assert_eq!(route.total, 315);
+            assert!(stats.expanded <= dijkstra.expanded);
+        }
+    }
+
+    #[test]
+    fn dial_matches_a_star() {
+        let map = parse(EXAMPLE);
+        assert_eq!(dial(&map), 40);
+        assert_eq!(part2_dial(&map), 315);
+    }
+
+    #[test]
+    #[should_panic(expected = "risk must be 1-9")]
+    fn zero_risk() {
+        // dial() would lose the free step into the bucket it's draining.
+        parse("10\n11");
+    }
+
+    #[test]
+    #[should_panic(expected = "dial() needs risks of 1-9, not 0")]
+    fn dial_zero_risk() {
+        // Skip parse() and its check.
+        let map = Map {
+            cells: vec![1, 0, 1, 1],
+            width: 2,
+            height: 2,
+        };
+        dial(&map);
+    }
 }
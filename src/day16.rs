@@ -70,57 +70,229 @@ fn parse_packet(bits: &mut &Slice) -> Packet {
 //    that represents the number of sub-packets immediately contained by this packet.
 //
 fn parse_subpackets(bits: &mut &Slice) -> Vec<Packet> {
+    let mut subpackets = Vec::new();
+
+    match parse_subpacket_length(bits) {
+        SubpacketLength::Bits(num_subpacket_bits) => {
+            let mut subpacket_bits = &bits[..num_subpacket_bits];
+            while !subpacket_bits.is_empty() {
+                subpackets.push(parse_packet(&mut subpacket_bits));
+            }
+            *bits = &bits[num_subpacket_bits..];
+        }
+        SubpacketLength::Count(num_subpackets) => {
+            subpackets.reserve(num_subpackets as usize);
+
+            for _ in 0..num_subpackets {
+                subpackets.push(parse_packet(bits));
+            }
+        }
+    }
+
+    subpackets
+}
+
+/// How an operator packet says where its sub-packets end
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SubpacketLength {
+    /// The sub-packets take up exactly this many bits
+    Bits(usize),
+    /// The operator has exactly this many immediate sub-packets
+    Count(u16),
+}
+
+fn parse_subpacket_length(bits: &mut &Slice) -> SubpacketLength {
     let length_type = bits[0];
     *bits = &bits[1..];
 
-    let mut subpackets = Vec::new();
-
     if !length_type {
         let num_subpacket_bits = bits[0..15]
             .iter()
             .fold(0usize, |acc, b| (acc << 1) | *b as usize);
         *bits = &bits[15..];
-
-        let mut subpacket_bits = &bits[..num_subpacket_bits];
-        while !subpacket_bits.is_empty() {
-            subpackets.push(parse_packet(&mut subpacket_bits));
-        }
-        *bits = &bits[num_subpacket_bits..];
+        SubpacketLength::Bits(num_subpacket_bits)
     } else {
         let num_subpackets = bits[0..11]
             .iter()
             .fold(0u16, |acc, b| (acc << 1) | *b as u16);
         *bits = &bits[11..];
+        SubpacketLength::Count(num_subpackets)
+    }
+}
 
-        subpackets.reserve(num_subpackets as usize);
+/// One packet header (plus its value, for literals),
+/// as seen by a pre-order walk of the transmission.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PacketEvent {
+    Literal {
+        version: u8,
+        value: i64,
+    },
+    Operator {
+        version: u8,
+        op: u8,
+        length: SubpacketLength,
+    },
+}
 
-        for _ in 0..num_subpackets {
-            subpackets.push(parse_packet(bits));
+impl PacketEvent {
+    pub fn version(&self) -> u8 {
+        match self {
+            PacketEvent::Literal { version, .. } => *version,
+            PacketEvent::Operator { version, .. } => *version,
         }
     }
+}
 
-    subpackets
+/// Lazily walks a transmission, yielding each packet in pre-order
+/// without building the tree.
+///
+/// Since the bitstream is already laid out in pre-order,
+/// this is just a cursor - no allocations, no recursion.
+pub struct PacketWalk<'a> {
+    bits: &'a Slice,
+    position: usize,
 }
 
-fn sum_versions(packet: &Packet) -> i64 {
-    static EMPTY: Vec<Packet> = vec![];
+impl<'a> PacketWalk<'a> {
+    pub fn new(bits: &'a Slice) -> Self {
+        Self { bits, position: 0 }
+    }
+
+    /// How many bits have been consumed so far.
+    ///
+    /// Callers that care about nesting compare this against
+    /// `SubpacketLength::Bits` to know when an operator's children end.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
 
-    let children: &Vec<_> = match &packet.contents {
-        PacketContents::Literal(_) => &EMPTY,
-        PacketContents::Operator(_, subs) => subs,
-    };
+impl<'a> Iterator for PacketWalk<'a> {
+    type Item = PacketEvent;
 
-    packet.version as i64
-        + children
-            .iter()
-            .fold(0i64, |acc, c| acc + sum_versions(c) as i64)
+    fn next(&mut self) -> Option<PacketEvent> {
+        let mut rest = &self.bits[self.position..];
+
+        // The smallest packet is a literal: a 6 bit header and one 5 bit group.
+        // Anything shorter is the zero padding at the end of the transmission.
+        // So is a run of 22 zeroes - that would be a sum with no sub-packets.
+        if rest.len() < 11 || rest[..std::cmp::min(22, rest.len())].not_any() {
+            return None;
+        }
+
+        let version = parse3(&mut rest);
+        let event = match parse3(&mut rest) {
+            4 => PacketEvent::Literal {
+                version,
+                value: parse_literal(&mut rest),
+            },
+            op => PacketEvent::Operator {
+                version,
+                op,
+                length: parse_subpacket_length(&mut rest),
+            },
+        };
+
+        self.position = self.bits.len() - rest.len();
+        Some(event)
+    }
+}
+
+/// Sums packet versions straight off the wire.
+pub fn stream_version_sum(bits: &Slice) -> i64 {
+    PacketWalk::new(bits).map(|e| e.version() as i64).sum()
+}
+
+/// An operator whose sub-packets we're still reading
+struct PendingOperator {
+    op: u8,
+    /// The bit position the children end at, or how many are left.
+    remaining: SubpacketLength,
+    acc: Option<i64>,
+}
+
+impl PendingOperator {
+    fn is_done(&self, position: usize) -> bool {
+        match self.remaining {
+            SubpacketLength::Bits(end) => position >= end,
+            SubpacketLength::Count(n) => n == 0,
+        }
+    }
+
+    fn push(&mut self, value: i64) {
+        if let SubpacketLength::Count(n) = &mut self.remaining {
+            *n -= 1;
+        }
+
+        self.acc = Some(match self.acc {
+            None => value,
+            Some(acc) => match self.op {
+                0 => acc + value,
+                1 => acc * value,
+                2 => std::cmp::min(acc, value),
+                3 => std::cmp::max(acc, value),
+                5 => (acc > value) as i64,
+                6 => (acc < value) as i64,
+                7 => (acc == value) as i64,
+                op => panic!("Unexpected op {}", op),
+            },
+        });
+    }
+}
+
+/// Evaluates a transmission as it's walked.
+///
+/// Memory is proportional to how deeply packets nest,
+/// not how many there are.
+pub fn stream_eval(bits: &Slice) -> i64 {
+    let mut walk = PacketWalk::new(bits);
+    let mut pending: Vec<PendingOperator> = Vec::new();
+
+    while let Some(event) = walk.next() {
+        let mut value = match event {
+            PacketEvent::Literal { value, .. } => value,
+            PacketEvent::Operator { op, length, .. } => {
+                // Store the end position for length-in-bits operators
+                // so we don't have to count down as we go.
+                let remaining = match length {
+                    SubpacketLength::Bits(b) => SubpacketLength::Bits(walk.position() + b),
+                    count => count,
+                };
+                pending.push(PendingOperator {
+                    op,
+                    remaining,
+                    acc: None,
+                });
+                continue;
+            }
+        };
+
+        // Hand the value up to its parent,
+        // and keep going as long as that finishes the parent.
+        loop {
+            match pending.last_mut() {
+                None => return value,
+                Some(parent) => {
+                    parent.push(value);
+                    if !parent.is_done(walk.position()) {
+                        break;
+                    }
+                    value = pending
+                        .pop()
+                        .unwrap()
+                        .acc
+                        .expect("operator without sub-packets");
+                }
+            }
+        }
+    }
+    panic!("Transmission ended inside an operator");
 }
 
 #[aoc(day16, part1)]
 pub fn part1(bytes: &[u8]) -> i64 {
-    let mut bits = bytes.view_bits::<Msb0>();
-    let packet = parse_packet(&mut bits);
-    sum_versions(&packet)
+    stream_version_sum(bytes.view_bits::<Msb0>())
 }
 
 fn eval(packet: &Packet) -> i64 {
@@ -196,6 +368,18 @@ pub fn part2(bytes: &[u8]) -> i64 {
 mod test {
     use super::*;
 
+    // The tree-walking version, to check the streaming one against.
+    fn sum_versions(packet: &Packet) -> i64 {
+        static EMPTY: Vec<Packet> = vec![];
+
+        let children: &Vec<_> = match &packet.contents {
+            PacketContents::Literal(_) => &EMPTY,
+            PacketContents::Operator(_, subs) => subs,
+        };
+
+        packet.version as i64 + children.iter().fold(0i64, |acc, c| acc + sum_versions(c))
+    }
+
     #[test]
     fn test_parse3() {
         let mut bits = [0xD2].view_bits::<Msb0>();
@@ -291,6 +475,64 @@ mod test {
         assert_eq!(sum_versions(&hex_to_packet("A0016C880162017C3686B18A3D4780")), 31);
     }
 
+    #[test]
+    fn test_walk() {
+        let bytes = hex::decode("38006F45291200").unwrap();
+        let events: Vec<_> = PacketWalk::new(bytes.view_bits::<Msb0>()).collect();
+        assert_eq!(
+            events,
+            [
+                PacketEvent::Operator {
+                    version: 1,
+                    op: 6,
+                    length: SubpacketLength::Bits(27)
+                },
+                PacketEvent::Literal {
+                    version: 6,
+                    value: 10
+                },
+                PacketEvent::Literal {
+                    version: 2,
+                    value: 20
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_stream_sums() {
+        for hex in [
+            "8A004A801A8002F478",
+            "620080001611562C8802118E34",
+            "C0015000016115A2E0802F182340",
+            "A0016C880162017C3686B18A3D4780",
+        ] {
+            let bytes = hex::decode(hex).unwrap();
+            assert_eq!(stream_version_sum(bytes.view_bits::<Msb0>()), sum_versions(&hex_to_packet(hex)));
+        }
+    }
+
+    #[test]
+    fn test_stream_eval() {
+        for hex in [
+            "C200B40A82",
+            "04005AC33890",
+            "880086C3E88112",
+            "CE00C43D881120",
+            "D8005AC2A8F0",
+            "F600BC2D8F",
+            "9C005AC2F8F0",
+            "9C0141080250320F1802104A08",
+        ] {
+            let bytes = hex::decode(hex).unwrap();
+            assert_eq!(
+                stream_eval(bytes.view_bits::<Msb0>()),
+                eval(&hex_to_packet(hex))
+            );
+        }
+    }
+
     #[test]
     fn test_eval() {
         // C200B40A82 finds the sum of 1 and 2, resulting in the value 3.
@@ -1,22 +1,25 @@
 use aoc_runner_derive::aoc;
 
-use std::fmt;
+use std::{fmt, ops};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum SnailElement {
-    Num(u8),
+pub enum SnailElement {
+    /// Parsed numbers are at most u32::MAX, and exploding and splitting
+    /// never change the sum of a number's regular numbers,
+    /// so these have plenty of room to grow.
+    Num(u64),
     Pair(SnailPair),
 }
 
 impl SnailElement {
-    fn left_leaf(&mut self) -> &mut u8 {
+    fn left_leaf(&mut self) -> &mut u64 {
         match self {
             SnailElement::Num(i) => i,
             SnailElement::Pair(p) => p.left.left_leaf(),
         }
     }
 
-    fn right_leaf(&mut self) -> &mut u8 {
+    fn right_leaf(&mut self) -> &mut u64 {
         match self {
             SnailElement::Num(i) => i,
             SnailElement::Pair(p) => p.right.right_leaf(),
@@ -34,7 +37,7 @@ impl fmt::Display for SnailElement {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-struct SnailPair {
+pub struct SnailPair {
     left: Box<SnailElement>,
     right: Box<SnailElement>,
 }
@@ -45,49 +48,248 @@ impl fmt::Display for SnailPair {
     }
 }
 
-fn add(left: SnailElement, right: SnailElement) -> SnailPair {
+fn join(left: SnailElement, right: SnailElement) -> SnailPair {
     let left = Box::new(left);
     let right = Box::new(right);
-    let mut pair = SnailPair { left, right };
+    SnailPair { left, right }
+}
 
-    while reduce(&mut pair) {}
-    pair
+/// How to split a number that's too big
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitRounding {
+    /// The left element rounds down and the right rounds up, like the puzzle.
+    LeftDown,
+    /// The left element rounds up and the right rounds down.
+    LeftUp,
 }
 
-fn reduce(pair: &mut SnailPair) -> bool {
-    explode(pair) || split(pair)
+/// Knobs for how snailfish numbers reduce and take their magnitude.
+///
+/// `Default` gives the puzzle's rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReductionRules {
+    /// Pairs nested inside this many pairs explode.
+    explode_depth: usize,
+    /// Regular numbers this large or larger split.
+    split_threshold: u8,
+    split_rounding: SplitRounding,
+    /// A pair's magnitude is (left * .0) + (right * .1)
+    magnitude_weights: (i64, i64),
 }
 
-fn split(pair: &mut SnailPair) -> bool {
-    // Bail early from the left side so that we always take the leftmost split.
-    match &mut *pair.left {
-        SnailElement::Num(_) => {
-            if split_num(&mut *pair.left) {
-                return true;
+/// Rules that would make reduction go on forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulesError {
+    /// At depth 0, the whole number is a pair that "explodes" into itself.
+    ExplodeDepthZero,
+    /// Splitting anything under 2 gives a pair with that number in it again.
+    SplitThresholdTooSmall(u8),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::ExplodeDepthZero => write!(f, "explode depth must be at least 1"),
+            RulesError::SplitThresholdTooSmall(t) => {
+                write!(f, "split threshold must be at least 2, not {}", t)
             }
         }
-        SnailElement::Pair(p) => {
-            if split(p) {
-                return true;
-            }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+impl ReductionRules {
+    pub const PUZZLE: ReductionRules = ReductionRules {
+        explode_depth: 4,
+        split_threshold: 10,
+        split_rounding: SplitRounding::LeftDown,
+        magnitude_weights: (3, 2),
+    };
+
+    pub fn new(
+        explode_depth: usize,
+        split_threshold: u8,
+        split_rounding: SplitRounding,
+        magnitude_weights: (i64, i64),
+    ) -> Result<Self, RulesError> {
+        if explode_depth == 0 {
+            return Err(RulesError::ExplodeDepthZero);
+        }
+        if split_threshold < 2 {
+            return Err(RulesError::SplitThresholdTooSmall(split_threshold));
+        }
+        Ok(Self {
+            explode_depth,
+            split_threshold,
+            split_rounding,
+            magnitude_weights,
+        })
+    }
+
+    pub fn explode_depth(&self) -> usize {
+        self.explode_depth
+    }
+
+    pub fn split_threshold(&self) -> u8 {
+        self.split_threshold
+    }
+
+    pub fn split_rounding(&self) -> SplitRounding {
+        self.split_rounding
+    }
+
+    pub fn magnitude_weights(&self) -> (i64, i64) {
+        self.magnitude_weights
+    }
+}
+
+impl Default for ReductionRules {
+    fn default() -> Self {
+        Self::PUZZLE
+    }
+}
+
+pub fn add(left: SnailElement, right: SnailElement) -> SnailPair {
+    add_with(left, right, &ReductionRules::PUZZLE)
+}
+
+pub fn add_with(left: SnailElement, right: SnailElement, rules: &ReductionRules) -> SnailPair {
+    let mut pair = join(left, right);
+
+    while reduce_with(&mut pair, rules) {}
+    pair
+}
+
+/// Which way to go from a pair to reach one of its elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A path from the root of a number, e.g., LRR
+pub type Path = Vec<Side>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    Explode,
+    Split,
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reduction::Explode => write!(f, "explode"),
+            Reduction::Split => write!(f, "split"),
         }
+    }
+}
+
+/// A single reduction, recorded by `add_traced()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReductionStep {
+    pub action: Reduction,
+    /// The pair that exploded or the number that split
+    pub path: Path,
+    /// The whole number, printed after this step
+    pub result: String,
+}
+
+/// Formats the step like the puzzle's worked example does,
+/// e.g., `after explode:  [[[[0,7],4],[7,[[8,4],9]]],[1,1]]`
+impl fmt::Display for ReductionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = format!("after {}:", self.action);
+        write!(f, "{:<16}{}", action, self.result)
+    }
+}
+
+/// Like `add()`, but records every step along the way.
+pub fn add_traced(left: SnailElement, right: SnailElement) -> (SnailPair, Vec<ReductionStep>) {
+    add_traced_with(left, right, &ReductionRules::PUZZLE)
+}
+
+pub fn add_traced_with(
+    left: SnailElement,
+    right: SnailElement,
+    rules: &ReductionRules,
+) -> (SnailPair, Vec<ReductionStep>) {
+    let mut pair = join(left, right);
+    let mut trace = Vec::new();
+
+    while let Some((action, path)) = reduce_at(&mut pair, rules) {
+        let result = pair.to_string();
+        trace.push(ReductionStep {
+            action,
+            path,
+            result,
+        });
+    }
+    (pair, trace)
+}
+
+pub fn reduce(pair: &mut SnailPair) -> bool {
+    reduce_with(pair, &ReductionRules::PUZZLE)
+}
+
+pub fn reduce_with(pair: &mut SnailPair, rules: &ReductionRules) -> bool {
+    reduce_at(pair, rules).is_some()
+}
+
+/// Reduces once, returning what happened and where.
+pub fn reduce_at(pair: &mut SnailPair, rules: &ReductionRules) -> Option<(Reduction, Path)> {
+    if let Some(path) = explode_at(pair, rules) {
+        return Some((Reduction::Explode, path));
+    }
+    split_at(pair, rules).map(|path| (Reduction::Split, path))
+}
+
+fn split_at(pair: &mut SnailPair, rules: &ReductionRules) -> Option<Path> {
+    let mut path = Vec::new();
+    if split_rec(pair, &mut path, rules) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn split_rec(pair: &mut SnailPair, path: &mut Path, rules: &ReductionRules) -> bool {
+    // Bail early from the left side so that we always take the leftmost split.
+    path.push(Side::Left);
+    let left_split = match &mut *pair.left {
+        SnailElement::Num(_) => split_num(&mut pair.left, rules),
+        SnailElement::Pair(p) => split_rec(p, path, rules),
     };
+    if left_split {
+        return true;
+    }
+    path.pop();
 
-    match &mut *pair.right {
-        SnailElement::Num(_) => split_num(&mut *pair.right),
-        SnailElement::Pair(p) => split(p),
+    path.push(Side::Right);
+    let right_split = match &mut *pair.right {
+        SnailElement::Num(_) => split_num(&mut pair.right, rules),
+        SnailElement::Pair(p) => split_rec(p, path, rules),
+    };
+    if !right_split {
+        path.pop();
     }
+    right_split
 }
 
-fn split_num(elem: &mut SnailElement) -> bool {
+fn split_num(elem: &mut SnailElement, rules: &ReductionRules) -> bool {
     match elem {
         SnailElement::Num(n) => {
-            if *n >= 10 {
+            if *n >= u64::from(rules.split_threshold) {
                 let round_down = *n / 2;
-                let round_up = (*n + 1) / 2;
+                let round_up = n.div_ceil(2);
+                let (l, r) = match rules.split_rounding {
+                    SplitRounding::LeftDown => (round_down, round_up),
+                    SplitRounding::LeftUp => (round_up, round_down),
+                };
                 let p = SnailPair {
-                    left: Box::new(SnailElement::Num(round_down)),
-                    right: Box::new(SnailElement::Num(round_up)),
+                    left: Box::new(SnailElement::Num(l)),
+                    right: Box::new(SnailElement::Num(r)),
                 };
                 *elem = SnailElement::Pair(p);
                 true
@@ -99,30 +301,52 @@ fn split_num(elem: &mut SnailElement) -> bool {
     }
 }
 
+#[cfg(test)]
 fn explode(pair: &mut SnailPair) -> bool {
-    explode_rec(pair, 0) != Splode::None
+    explode_at(pair, &ReductionRules::PUZZLE).is_some()
+}
+
+fn explode_at(pair: &mut SnailPair, rules: &ReductionRules) -> Option<Path> {
+    let mut path = Vec::new();
+    if explode_rec(pair, 0, &mut path, rules) != Splode::None {
+        Some(path)
+    } else {
+        None
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 enum Splode {
     None,
     Boom,
-    PropagateLeft(u8),
-    PropagateRight(u8),
-    PropagateBoth((u8, u8)),
+    PropagateLeft(u64),
+    PropagateRight(u64),
+    PropagateBoth((u64, u64)),
 }
 
-fn explode_rec(pair: &mut SnailPair, depth: usize) -> Splode {
+/// Explodes the leftmost pair nested in four
+/// (or `rules.explode_depth`) others.
+///
+/// On success, `path` is left pointing at the pair that exploded.
+fn explode_rec(
+    pair: &mut SnailPair,
+    depth: usize,
+    path: &mut Path,
+    rules: &ReductionRules,
+) -> Splode {
     match (&*pair.left, &*pair.right) {
-        (SnailElement::Num(l), SnailElement::Num(r)) if depth >= 4 => {
+        (SnailElement::Num(l), SnailElement::Num(r)) if depth >= rules.explode_depth => {
             return Splode::PropagateBoth((*l, *r))
         }
         _ => (),
     };
 
     if let SnailElement::Pair(p) = &mut *pair.left {
-        match explode_rec(p, depth + 1) {
-            Splode::None => {}
+        path.push(Side::Left);
+        match explode_rec(p, depth + 1, path, rules) {
+            Splode::None => {
+                path.pop();
+            }
             // Bail early on all explosions so we only consider the leftmost
             Splode::Boom => {
                 return Splode::Boom;
@@ -143,8 +367,11 @@ fn explode_rec(pair: &mut SnailPair, depth: usize) -> Splode {
     }
 
     if let SnailElement::Pair(p) = &mut *pair.right {
-        match explode_rec(p, depth + 1) {
-            Splode::None => {}
+        path.push(Side::Right);
+        match explode_rec(p, depth + 1, path, rules) {
+            Splode::None => {
+                path.pop();
+            }
             Splode::Boom => {
                 return Splode::Boom;
             }
@@ -178,48 +405,334 @@ fn add_lines(input: &str) -> SnailPair {
         .expect("Odd number of lines")
 }
 
-fn parse_line(input: &str) -> SnailPair {
-    let mut bytes = input.as_bytes();
-    let pair = parse_pair(&mut bytes);
-    assert_eq!(bytes.len(), 0);
-    pair
+pub fn parse_line(input: &str) -> SnailPair {
+    input.parse().unwrap_or_else(|e| panic!("{}", e))
 }
 
-fn parse_pair(bytes: &mut &[u8]) -> SnailPair {
-    assert_eq!(bytes[0], b'[');
-    *bytes = &bytes[1..];
+/// What went wrong parsing a snailfish number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// We wanted the given thing, but found something else (or nothing).
+    Expected(&'static str, Option<char>),
+    /// A regular number was bigger than u32::MAX
+    NumberTooLarge,
+    /// There was something after the outermost pair
+    TrailingInput,
+}
 
-    let left = Box::new(parse_element(bytes));
-    assert_eq!(bytes[0], b',');
-    *bytes = &bytes[1..];
-    let right = Box::new(parse_element(bytes));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the input
+    pub position: usize,
+}
 
-    assert_eq!(bytes[0], b']');
-    *bytes = &bytes[1..];
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::Expected(what, Some(c)) => {
+                write!(f, "expected {}, found '{}'", what, c)?
+            }
+            ParseErrorKind::Expected(what, None) => {
+                write!(f, "expected {}, found end of input", what)?
+            }
+            ParseErrorKind::NumberTooLarge => write!(f, "number too large")?,
+            ParseErrorKind::TrailingInput => write!(f, "trailing input")?,
+        };
+        write!(f, " at position {}", self.position)
+    }
+}
 
-    SnailPair { left, right }
+impl std::error::Error for ParseError {}
+
+impl std::str::FromStr for SnailPair {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, ParseError> {
+        let mut bytes = input.as_bytes();
+
+        // Parse functions leave `bytes` at the problem when they fail.
+        let at = |bytes: &[u8], kind| ParseError {
+            kind,
+            position: input.len() - bytes.len(),
+        };
+
+        let pair = parse_pair(&mut bytes).map_err(|kind| at(bytes, kind))?;
+        skip_whitespace(&mut bytes);
+        if !bytes.is_empty() {
+            return Err(at(bytes, ParseErrorKind::TrailingInput));
+        }
+        Ok(pair)
+    }
 }
 
-fn parse_element(bytes: &mut &[u8]) -> SnailElement {
-    if (b'0'..=b'9').contains(&bytes[0]) {
-        let val = bytes[0] - b'0';
+fn skip_whitespace(bytes: &mut &[u8]) {
+    while let Some(b) = bytes.first() {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
         *bytes = &bytes[1..];
-        SnailElement::Num(val)
-    } else {
-        SnailElement::Pair(parse_pair(bytes))
     }
 }
 
-fn magnitude(pair: &SnailPair) -> i64 {
-    let l = element_magnitude(&*pair.left);
-    let r = element_magnitude(&*pair.right);
-    l * 3 + r * 2
+fn expect(bytes: &mut &[u8], what: &'static str, c: u8) -> Result<(), ParseErrorKind> {
+    skip_whitespace(bytes);
+    match bytes.first() {
+        Some(b) if *b == c => {
+            *bytes = &bytes[1..];
+            Ok(())
+        }
+        other => Err(ParseErrorKind::Expected(what, other.map(|b| *b as char))),
+    }
+}
+
+fn parse_pair(bytes: &mut &[u8]) -> Result<SnailPair, ParseErrorKind> {
+    expect(bytes, "'['", b'[')?;
+    let left = Box::new(parse_element(bytes)?);
+    expect(bytes, "','", b',')?;
+    let right = Box::new(parse_element(bytes)?);
+    expect(bytes, "']'", b']')?;
+
+    Ok(SnailPair { left, right })
 }
 
-fn element_magnitude(elem: &SnailElement) -> i64 {
+fn parse_element(bytes: &mut &[u8]) -> Result<SnailElement, ParseErrorKind> {
+    skip_whitespace(bytes);
+    match bytes.first() {
+        Some(b) if b.is_ascii_digit() => parse_num(bytes).map(SnailElement::Num),
+        Some(b'[') => parse_pair(bytes).map(SnailElement::Pair),
+        other => Err(ParseErrorKind::Expected(
+            "a number or pair",
+            other.map(|b| *b as char),
+        )),
+    }
+}
+
+fn parse_num(bytes: &mut &[u8]) -> Result<u64, ParseErrorKind> {
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+
+    let mut val = 0u64;
+    for d in &bytes[..digits] {
+        val = val * 10 + (d - b'0') as u64;
+        if val > u32::MAX as u64 {
+            return Err(ParseErrorKind::NumberTooLarge);
+        }
+    }
+    *bytes = &bytes[digits..];
+    Ok(val)
+}
+
+pub fn magnitude(pair: &SnailPair) -> i64 {
+    magnitude_with(pair, &ReductionRules::PUZZLE)
+}
+
+pub fn magnitude_with(pair: &SnailPair, rules: &ReductionRules) -> i64 {
+    let l = element_magnitude(&pair.left, rules);
+    let r = element_magnitude(&pair.right, rules);
+    let (lw, rw) = rules.magnitude_weights;
+    l * lw + r * rw
+}
+
+fn element_magnitude(elem: &SnailElement, rules: &ReductionRules) -> i64 {
     match elem {
         SnailElement::Num(n) => *n as i64,
-        SnailElement::Pair(p) => magnitude(p),
+        SnailElement::Pair(p) => magnitude_with(p, rules),
+    }
+}
+
+/// A regular number in a flattened snailfish number,
+/// along with how many pairs it's nested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Leaf {
+    value: u8,
+    depth: u8,
+}
+
+/// A snailfish number as its leaves, left to right.
+///
+/// Every operation we need only cares about a number's neighbors in that order
+/// (and how deep they are), so we can skip the tree entirely.
+///
+/// This only does the puzzle's rules (`ReductionRules::PUZZLE`),
+/// and only holds reduced numbers - see `TryFrom<&SnailPair>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatSnail {
+    leaves: Vec<Leaf>,
+}
+
+impl FlatSnail {
+    pub fn reduce(&mut self) -> bool {
+        self.explode() || self.split()
+    }
+
+    fn explode(&mut self) -> bool {
+        // A pair nested inside four others has leaves at depth 5.
+        // Since we start reduced, those leaves are always a pair of regular numbers.
+        // (If they aren't, we'd explode the wrong leaves.)
+        let i = match self.leaves.iter().position(|l| l.depth > 4) {
+            Some(i) => i,
+            None => return false,
+        };
+        let (l, r) = (self.leaves[i], self.leaves[i + 1]);
+        assert_eq!(l.depth, r.depth, "exploding something that isn't a pair");
+
+        if i > 0 {
+            self.leaves[i - 1].value += l.value;
+        }
+        if let Some(next) = self.leaves.get_mut(i + 2) {
+            next.value += r.value;
+        }
+
+        self.leaves[i] = Leaf {
+            value: 0,
+            depth: l.depth - 1,
+        };
+        self.leaves.remove(i + 1);
+        true
+    }
+
+    fn split(&mut self) -> bool {
+        let i = match self.leaves.iter().position(|l| l.value >= 10) {
+            Some(i) => i,
+            None => return false,
+        };
+        let Leaf { value, depth } = self.leaves[i];
+
+        self.leaves[i] = Leaf {
+            value: value / 2,
+            depth: depth + 1,
+        };
+        self.leaves.insert(
+            i + 1,
+            Leaf {
+                value: value.div_ceil(2),
+                depth: depth + 1,
+            },
+        );
+        true
+    }
+
+    pub fn magnitude(&self) -> i64 {
+        // Fold sibling leaves together as soon as we see both,
+        // the same way a shift-reduce parser would.
+        let mut stack: Vec<(i64, u8)> = Vec::with_capacity(self.leaves.len());
+
+        for leaf in &self.leaves {
+            let mut top = (leaf.value as i64, leaf.depth);
+            while let Some(&(left, depth)) = stack.last() {
+                if depth != top.1 {
+                    break;
+                }
+                stack.pop();
+                top = (left * 3 + top.0 * 2, depth - 1);
+            }
+            stack.push(top);
+        }
+
+        assert_eq!(stack.len(), 1);
+        stack[0].0
+    }
+}
+
+impl ops::Add<&FlatSnail> for FlatSnail {
+    type Output = FlatSnail;
+
+    fn add(mut self, right: &FlatSnail) -> Self {
+        self.leaves.extend_from_slice(&right.leaves);
+        for leaf in &mut self.leaves {
+            leaf.depth += 1;
+        }
+
+        while self.reduce() {}
+        self
+    }
+}
+
+/// A number `FlatSnail` can't hold because it isn't reduced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotReduced {
+    /// A pair nested inside four others (which should have exploded)
+    TooDeep,
+    /// A regular number over 9 (which should have split)
+    TooLarge(u64),
+}
+
+impl fmt::Display for NotReduced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotReduced::TooDeep => write!(f, "a pair is nested inside four others"),
+            NotReduced::TooLarge(n) => write!(f, "{} is more than 9", n),
+        }
+    }
+}
+
+impl std::error::Error for NotReduced {}
+
+impl TryFrom<&SnailPair> for FlatSnail {
+    type Error = NotReduced;
+
+    fn try_from(pair: &SnailPair) -> Result<Self, NotReduced> {
+        fn flatten(
+            elem: &SnailElement,
+            depth: u8,
+            leaves: &mut Vec<Leaf>,
+        ) -> Result<(), NotReduced> {
+            match elem {
+                SnailElement::Num(value) => {
+                    if depth > 4 {
+                        return Err(NotReduced::TooDeep);
+                    }
+                    if *value > 9 {
+                        return Err(NotReduced::TooLarge(*value));
+                    }
+                    leaves.push(Leaf {
+                        value: *value as u8,
+                        depth,
+                    });
+                }
+                SnailElement::Pair(p) => {
+                    flatten(&p.left, depth + 1, leaves)?;
+                    flatten(&p.right, depth + 1, leaves)?;
+                }
+            }
+            Ok(())
+        }
+
+        let mut leaves = Vec::new();
+        flatten(&pair.left, 1, &mut leaves)?;
+        flatten(&pair.right, 1, &mut leaves)?;
+        Ok(Self { leaves })
+    }
+}
+
+impl From<&FlatSnail> for SnailPair {
+    fn from(flat: &FlatSnail) -> Self {
+        fn unflatten(leaves: &mut &[Leaf], depth: u8) -> SnailElement {
+            if leaves[0].depth == depth {
+                let val = leaves[0].value;
+                *leaves = &leaves[1..];
+                SnailElement::Num(val.into())
+            } else {
+                SnailElement::Pair(unflatten_pair(leaves, depth + 1))
+            }
+        }
+
+        fn unflatten_pair(leaves: &mut &[Leaf], depth: u8) -> SnailPair {
+            let left = Box::new(unflatten(leaves, depth));
+            let right = Box::new(unflatten(leaves, depth));
+            SnailPair { left, right }
+        }
+
+        let mut leaves = flat.leaves.as_slice();
+        let pair = unflatten_pair(&mut leaves, 1);
+        assert!(leaves.is_empty());
+        pair
+    }
+}
+
+impl fmt::Display for FlatSnail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", SnailPair::from(self))
     }
 }
 
@@ -254,10 +767,38 @@ fn part2(input: &str) -> i64 {
     max
 }
 
+// Same as above, but with flat numbers that don't need a
+// pile of boxes cloned and freed for every sum.
+#[aoc(day18, part2, Flat)]
+fn part2_flat(input: &str) -> i64 {
+    let nums: Vec<_> = input
+        .lines()
+        .map(|l| FlatSnail::try_from(&parse_line(l)).expect("Unreduced input"))
+        .collect();
+
+    let mut max = 0;
+
+    for (i, outer) in nums.iter().enumerate() {
+        for (j, inner) in nums.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let sum = (outer.clone() + inner).magnitude();
+            if sum > max {
+                max = sum;
+            }
+        }
+    }
+
+    max
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    use itertools::Itertools;
+
     #[test]
     fn smoke_line_parse() {
         assert_eq!("[[1,2],3]", parse_line("[[1,2],3]").to_string());
@@ -386,4 +927,239 @@ mod test {
         let sum = add(left, right);
         assert_eq!(sum.to_string(), "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]");
     }
+
+    #[test]
+    fn flat_round_trip() {
+        for line in [
+            "[[1,2],3]",
+            "[9,[8,7]]",
+            "[[[[1,3],[5,3]],[[1,3],[8,7]]],[[[4,9],[6,9]],[[8,2],[7,3]]]]",
+        ] {
+            let pair = parse_line(line);
+            let flat = FlatSnail::try_from(&pair).unwrap();
+            assert_eq!(flat.to_string(), line);
+            assert_eq!(SnailPair::from(&flat), pair);
+        }
+    }
+
+    #[test]
+    fn large_numbers() {
+        // Exploding [1,200] gives its right neighbor 300.
+        let big = SnailElement::Pair(parse_line("[[[[1,200],100],0],0]"));
+        let sum = add(big, SnailElement::Num(0));
+        assert!(FlatSnail::try_from(&sum).is_ok());
+
+        let huge = SnailElement::Pair(parse_line("[[[[40000,40000],40000],0],0]"));
+        let (sum, trace) = add_traced(huge, SnailElement::Num(0));
+        assert_eq!(trace[0].result, "[[[[0,80000],0],0],0]");
+        assert!(FlatSnail::try_from(&sum).is_ok());
+
+        let max = parse_line("[4294967295,4294967295]");
+        assert_eq!(max.to_string(), "[4294967295,4294967295]");
+        assert_eq!(magnitude(&max), 5 * 4294967295);
+    }
+
+    #[test]
+    fn flat_rejects_unreduced() {
+        // The boxed version copes with these, but the flat one can't.
+        assert_eq!(
+            FlatSnail::try_from(&parse_line("[[[[1,[2,3]],4],5],6]")),
+            Err(NotReduced::TooDeep)
+        );
+        assert_eq!(
+            FlatSnail::try_from(&parse_line("[[1,2],10]")),
+            Err(NotReduced::TooLarge(10))
+        );
+        assert!(FlatSnail::try_from(&parse_line("[[[[1,2],3],4],9]")).is_ok());
+    }
+
+    #[test]
+    fn flat_matches_boxed() {
+        let input = r"[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]
+[[[5,[2,8]],4],[5,[[9,9],0]]]
+[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]
+[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]
+[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]
+[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]
+[[[[5,4],[7,7]],8],[[8,3],8]]
+[[9,3],[[9,9],[6,[4,9]]]]
+[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]
+[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]";
+
+        let flat_sum = input
+            .lines()
+            .map(|l| FlatSnail::try_from(&parse_line(l)).unwrap())
+            .reduce(|acc, n| acc + &n)
+            .unwrap();
+        assert_eq!(
+            flat_sum.to_string(),
+            "[[[[6,6],[7,6]],[[7,7],[7,0]]],[[[7,7],[7,7]],[[7,8],[9,9]]]]"
+        );
+        assert_eq!(flat_sum.magnitude(), 4140);
+        assert_eq!(flat_sum.magnitude(), magnitude(&add_lines(input)));
+
+        assert_eq!(part2_flat(input), 3993);
+        assert_eq!(part2_flat(input), part2(input));
+    }
+
+    #[test]
+    fn traced_addition() {
+        let left = SnailElement::Pair(parse_line("[[[[4,3],4],4],[7,[[8,4],9]]]"));
+        let right = SnailElement::Pair(parse_line("[1,1]"));
+
+        // Straight from the puzzle text
+        let expected = "\
+after explode:  [[[[0,7],4],[7,[[8,4],9]]],[1,1]]
+after explode:  [[[[0,7],4],[15,[0,13]]],[1,1]]
+after split:    [[[[0,7],4],[[7,8],[0,13]]],[1,1]]
+after split:    [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]
+after explode:  [[[[0,7],4],[[7,8],[6,0]]],[8,1]]";
+
+        let (sum, trace) = add_traced(left.clone(), right.clone());
+        assert_eq!(trace.iter().join("\n"), expected);
+        assert_eq!(sum, add(left, right));
+
+        use Side::*;
+        assert_eq!(trace[0].path, [Left, Left, Left, Left]);
+        assert_eq!(trace[1].path, [Left, Right, Right, Left]);
+        assert_eq!(trace[2].path, [Left, Right, Left]);
+        assert_eq!(trace[3].path, [Left, Right, Right, Right]);
+        assert_eq!(trace[4].path, [Left, Right, Right, Right]);
+    }
+
+    #[test]
+    fn parse_unreduced() {
+        // Every intermediate state in the addition example
+        for line in [
+            "[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]",
+            "[[[[0,7],4],[15,[0,13]]],[1,1]]",
+            "[[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+            "[[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+        ] {
+            assert_eq!(parse_line(line).to_string(), line);
+        }
+
+        assert_eq!(parse_line(" [ [1, 22] ,\t3 ]\n").to_string(), "[[1,22],3]");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |s: &str| s.parse::<SnailPair>().unwrap_err();
+
+        assert_eq!(
+            err("[1,2"),
+            ParseError {
+                kind: ParseErrorKind::Expected("']'", None),
+                position: 4
+            }
+        );
+        assert_eq!(
+            err("[1;2]"),
+            ParseError {
+                kind: ParseErrorKind::Expected("','", Some(';')),
+                position: 2
+            }
+        );
+        assert_eq!(
+            err("[1,[x,2]]"),
+            ParseError {
+                kind: ParseErrorKind::Expected("a number or pair", Some('x')),
+                position: 4
+            }
+        );
+        assert_eq!(
+            err("[1,4294967296]"),
+            ParseError {
+                kind: ParseErrorKind::NumberTooLarge,
+                position: 3
+            }
+        );
+        assert_eq!(
+            err("[1,2]]"),
+            ParseError {
+                kind: ParseErrorKind::TrailingInput,
+                position: 5
+            }
+        );
+        assert_eq!(
+            err("[1,2").to_string(),
+            "expected ']', found end of input at position 4"
+        );
+    }
+
+    #[test]
+    fn shallow_rules() {
+        let lr = SplitRounding::LeftDown;
+        let rules = ReductionRules::new(1, 10, lr, (3, 2)).unwrap();
+        let left = SnailElement::Pair(parse_line("[1,2]"));
+        let right = SnailElement::Pair(parse_line("[3,4]"));
+
+        // [[1,2],[3,4]] -> [0,[5,4]] -> [5,0]
+        let sum = add_with(left.clone(), right.clone(), &rules);
+        assert_eq!(sum.to_string(), "[5,0]");
+        assert_eq!(magnitude_with(&sum, &rules), 15);
+
+        // ...then [[2,3],0] -> [0,3]
+        let rules = ReductionRules::new(1, 5, lr, (3, 2)).unwrap();
+        let (sum, trace) = add_traced_with(left.clone(), right.clone(), &rules);
+        assert_eq!(sum.to_string(), "[0,3]");
+        assert_eq!(
+            trace.iter().map(|s| s.action).collect::<Vec<_>>(),
+            [
+                Reduction::Explode,
+                Reduction::Explode,
+                Reduction::Split,
+                Reduction::Explode
+            ]
+        );
+
+        // ...or [[3,2],0] -> [0,2]
+        let rules = ReductionRules::new(1, 5, SplitRounding::LeftUp, (3, 2)).unwrap();
+        assert_eq!(add_with(left, right, &rules).to_string(), "[0,2]");
+
+        let sum_leaves = ReductionRules::new(4, 10, lr, (1, 1)).unwrap();
+        assert_eq!(
+            magnitude_with(&parse_line("[[1,2],[3,[4,5]]]"), &sum_leaves),
+            15
+        );
+    }
+
+    #[test]
+    fn endless_rules() {
+        let lr = SplitRounding::LeftDown;
+        // [1,2] would explode into itself forever...
+        assert_eq!(
+            ReductionRules::new(0, 10, lr, (3, 2)),
+            Err(RulesError::ExplodeDepthZero)
+        );
+        // ...and 1 would split into [0,1] forever.
+        assert_eq!(
+            ReductionRules::new(4, 1, lr, (3, 2)),
+            Err(RulesError::SplitThresholdTooSmall(1))
+        );
+        assert_eq!(
+            ReductionRules::new(4, 0, lr, (3, 2)),
+            Err(RulesError::SplitThresholdTooSmall(0))
+        );
+
+        // 2 splits into [1,1], which is fine.
+        let rules = ReductionRules::new(4, 2, lr, (3, 2)).unwrap();
+        let left = SnailElement::Pair(parse_line("[1,2]"));
+        let right = SnailElement::Num(1);
+        assert_eq!(add_with(left, right, &rules).to_string(), "[[1,[1,1]],1]");
+        assert_eq!(
+            ReductionRules::new(4, 10, lr, (3, 2)),
+            Ok(ReductionRules::PUZZLE)
+        );
+    }
+
+    #[test]
+    fn high_split_threshold() {
+        // Numbers under the threshold can still add up past 255 when they explode.
+        let rules = ReductionRules::new(4, 200, SplitRounding::LeftDown, (3, 2)).unwrap();
+        let left = SnailElement::Pair(parse_line("[[[[150,150],150],0],0]"));
+        let sum = add_with(left, SnailElement::Num(0), &rules);
+        // [[[[0,300],0],0],0] splits, then explodes
+        assert_eq!(sum.to_string(), "[[[[150,0],150],0],0]");
+    }
 }
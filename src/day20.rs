@@ -4,57 +4,39 @@ use std::fmt::{Debug, Error, Formatter};
 
 use bitvec::prelude::*;
 
-struct Image {
-    pixels: BitVec,
-    width: usize,
-    height: usize,
+use crate::automaton::{Automaton, Boundary, Neighborhood};
 
-    /// Is the infinite space around us lit?
-    lit_expanse: bool,
+struct Image {
+    grid: Automaton<bool>,
 }
 
 impl Image {
-    /// Get a pixel - out-of-bounds values are furnished by the expanse
-    fn pixel(&self, x: isize, y: isize) -> usize {
-        let x = x as usize;
-        let y = y as usize;
-
-        (if x >= self.width || y >= self.height {
-            self.lit_expanse
-        } else {
-            self.pixels[x + y * self.width]
-        }) as usize
+    fn lit_pixels(&self) -> usize {
+        self.grid.cells().iter().filter(|p| **p).count()
     }
 
-    // Get the LUT index for the current pixel
-    fn kernel_lut_index(&self, x: isize, y: isize) -> usize {
-        // Overflow & underflow is fine, pixel() handles it above.
-        self.pixel(x - 1, y - 1) << 8
-            | self.pixel(x, y - 1) << 7
-            | self.pixel(x + 1, y - 1) << 6
-            | self.pixel(x - 1, y) << 5
-            | self.pixel(x, y) << 4
-            | self.pixel(x + 1, y) << 3
-            | self.pixel(x - 1, y + 1) << 2
-            | self.pixel(x, y + 1) << 1
-            | self.pixel(x + 1, y + 1)
+    /// Is the infinite space around us lit?
+    fn lit_expanse(&self) -> bool {
+        *self.grid.boundary() == Boundary::Infinite(true)
     }
 }
 
+/// Get the LUT index for a pixel's 3x3 kernel,
+/// read like a binary number from the top left.
+fn kernel_lut_index(kernel: &[bool]) -> usize {
+    kernel.iter().fold(0, |acc, p| (acc << 1) | *p as usize)
+}
+
 impl Debug for Image {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let c = if self.pixels[x + y * self.width] {
-                    '#'
-                } else {
-                    '.'
-                };
+        for line in self.grid.cells().chunks(self.grid.width()) {
+            for pixel in line {
+                let c = if *pixel { '#' } else { '.' };
                 write!(f, "{}", c)?;
             }
             writeln!(f)?;
         }
-        if self.lit_expanse {
+        if self.lit_expanse() {
             writeln!(f, "Expanse is lit")?;
         } else {
             writeln!(f, "Expanse is dark")?;
@@ -101,7 +83,7 @@ fn parse_lut(line: &str) -> BitVec {
 }
 
 fn parse_image(lines: &mut std::str::Lines<'_>) -> Image {
-    let mut pixels = BitVec::new();
+    let mut pixels = Vec::new();
     let mut width: usize = 0;
     let mut height: usize = 0;
 
@@ -118,40 +100,18 @@ fn parse_image(lines: &mut std::str::Lines<'_>) -> Image {
     assert_eq!(width, height);
 
     Image {
-        pixels,
-        width,
-        height,
-        lit_expanse: false,
+        grid: Automaton::new(pixels, width, Boundary::Infinite(false)),
     }
 }
 
 fn enhance(image: &Image, lut: &BitSlice) -> Image {
-    // All kernels in the expanse will be all zeroes or all ones.
-    let lit_expanse: bool = if image.lit_expanse {
-        *lut.last().unwrap()
-    } else {
-        *lut.first().unwrap()
-    };
-
-    // Since our kernel is 3x3, the neighboring 2 pixels in every direction
-    // will be influenced by the previous image.
-    let width = image.width + 2 * 2;
-    let height = image.height + 2 * 2;
-    let mut pixels = BitVec::with_capacity(width * height);
-
-    for y in -2isize..(image.height as isize + 2) {
-        for x in -2isize..(image.width as isize + 2) {
-            let index = image.kernel_lut_index(x, y);
-            pixels.push(lut[index]);
-        }
-    }
-
-    Image {
-        pixels,
-        width,
-        height,
-        lit_expanse,
-    }
+    // The automaton handles the expanse for us: all kernels out there
+    // are all zeroes or all ones, and it grows the image by a pixel each way
+    // since those are influenced by the previous image.
+    let grid = image.grid.step(Neighborhood::SQUARE, |_, kernel| {
+        lut[kernel_lut_index(kernel)]
+    });
+    Image { grid }
 }
 
 #[aoc(day20, part1)]
@@ -159,7 +119,7 @@ fn part1(input: &str) -> usize {
     let Input { lut, mut image } = parse(input);
     image = enhance(&image, &lut);
     image = enhance(&image, &lut);
-    image.pixels.count_ones()
+    image.lit_pixels()
 }
 
 #[aoc(day20, part2)]
@@ -168,7 +128,7 @@ fn part2(input: &str) -> usize {
     for _ in 0..50 {
         image = enhance(&image, &lut);
     }
-    image.pixels.count_ones()
+    image.lit_pixels()
 }
 
 #[cfg(test)]
@@ -177,18 +137,23 @@ mod test {
 
     #[test]
     fn kernel_smoke() {
-        let img = Image {
-            width: 3,
-            height: 3,
-            pixels: bitvec![0, 0, 0, 1, 0, 0, 0, 1, 0],
-            lit_expanse: false,
+        let img = Automaton::new(
+            vec![false, false, false, true, false, false, false, true, false],
+            3,
+            Boundary::Infinite(false),
+        );
+
+        let index_at = |x, y| {
+            let mut kernel = Vec::new();
+            img.neighborhood_into(x, y, Neighborhood::SQUARE, &mut kernel);
+            kernel_lut_index(&kernel)
         };
 
-        assert_eq!(img.kernel_lut_index(1, 1), 34);
-        assert_eq!(img.kernel_lut_index(2, 1), 0b100);
-        assert_eq!(img.kernel_lut_index(3, 1), 0);
-        assert_eq!(img.kernel_lut_index(1, 0), 0b100);
-        assert_eq!(img.kernel_lut_index(0, -1), 0);
+        assert_eq!(index_at(1, 1), 34);
+        assert_eq!(index_at(2, 1), 0b100);
+        assert_eq!(index_at(3, 1), 0);
+        assert_eq!(index_at(1, 0), 0b100);
+        assert_eq!(index_at(0, -1), 0);
     }
 
     #[test]
@@ -208,11 +173,11 @@ mod test {
         image = enhance(&image, &lut);
         println!("{:?}", image);
 
-        assert_eq!(image.pixels.count_ones(), 35);
+        assert_eq!(image.lit_pixels(), 35);
 
         for _ in 2..50 {
             image = enhance(&image, &lut);
         }
-        assert_eq!(image.pixels.count_ones(), 3351);
+        assert_eq!(image.lit_pixels(), 3351);
     }
 }
@@ -1,5 +1,7 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 
+use crate::matrix::{Arithmetic, Matrix};
+
 type FishCounts = [i64; 9];
 
 #[aoc_generator(day6)]
@@ -22,6 +24,136 @@ fn fish_after_day(mut counts: FishCounts, day: usize) -> i64 {
     counts.iter().sum()
 }
 
+/// How lanternfish live and breed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifecycleParams {
+    /// What a fish's timer goes back to after it spawns
+    pub reset_timer: usize,
+    /// What a newborn's timer starts at
+    pub newborn_timer: usize,
+    /// If fish die, how many days old they are when they do
+    pub death_age: Option<usize>,
+}
+
+impl LifecycleParams {
+    /// Lanternfish as the puzzle describes them
+    pub const PUZZLE: Self = Self {
+        reset_timer: 6,
+        newborn_timer: 8,
+        death_age: None,
+    };
+}
+
+impl Default for LifecycleParams {
+    fn default() -> Self {
+        Self::PUZZLE
+    }
+}
+
+/// A school of fish, bucketed by age and timer
+///
+/// We don't know how old the starting fish are, so they're all newborns
+/// (age 0) as far as dying goes.
+#[derive(Debug, Clone)]
+pub struct Population {
+    params: LifecycleParams,
+    /// How many fish have each timer value, for each age.
+    /// If fish never die, age doesn't matter and everybody's in age 0.
+    counts: Vec<u128>,
+    timers: usize,
+}
+
+impl Population {
+    /// Starts with `by_timer[t]` fish whose timers are at `t`
+    pub fn new(params: LifecycleParams, by_timer: &[i64]) -> Self {
+        assert_ne!(params.death_age, Some(0), "fish have to live a little");
+
+        let timers = *[
+            params.reset_timer + 1,
+            params.newborn_timer + 1,
+            by_timer.len(),
+        ]
+        .iter()
+        .max()
+        .unwrap();
+        let ages = params.death_age.unwrap_or(1);
+
+        let mut counts = vec![0; ages * timers];
+        for (t, n) in by_timer.iter().enumerate() {
+            counts[t] = *n as u128;
+        }
+
+        Self {
+            params,
+            counts,
+            timers,
+        }
+    }
+
+    pub fn total(&self) -> u128 {
+        self.counts.iter().sum()
+    }
+
+    /// Every fish gets a day older and counts down.
+    /// Fish at zero spawn a newborn and reset, even if it's their last day.
+    pub fn step(&mut self) {
+        let mut next = vec![0; self.counts.len()];
+
+        for (i, n) in self.counts.iter().enumerate() {
+            if *n == 0 {
+                continue;
+            }
+            let (age, timer) = (i / self.timers, i % self.timers);
+
+            let timer = if timer == 0 {
+                next[self.params.newborn_timer] += n;
+                self.params.reset_timer
+            } else {
+                timer - 1
+            };
+
+            let age = match self.params.death_age {
+                Some(death) if age + 1 >= death => continue,
+                Some(_) => age + 1,
+                None => 0,
+            };
+            next[age * self.timers + timer] += n;
+        }
+
+        self.counts = next;
+    }
+
+    /// The population on each day, from today (day 0) through `days` from now
+    pub fn time_series(&mut self, days: usize) -> Vec<u128> {
+        let mut series = Vec::with_capacity(days + 1);
+        series.push(self.total());
+        for _day in 0..days {
+            self.step();
+            series.push(self.total());
+        }
+        series
+    }
+}
+
+/// The same rotation as a matrix: every fish counts down a day,
+/// and the ones at zero go back to 6 and spawn one at 8.
+fn fish_matrix() -> Matrix {
+    let mut m = Matrix::zero(9);
+    for timer in 1..9 {
+        m[(timer - 1, timer)] = 1;
+    }
+    m[(6, 0)] = 1;
+    m[(8, 0)] = 1;
+    m
+}
+
+/// Jumps straight to the given day, or `None` if the count doesn't fit.
+pub fn fish_after_day_jump(counts: &FishCounts, day: u64, arith: Arithmetic) -> Option<u128> {
+    let start: Vec<u128> = counts.iter().map(|c| *c as u128).collect();
+    let end = fish_matrix().pow(day, arith)?.apply(&start, arith)?;
+    arith.sum(end)
+}
+
 #[aoc(day6, part1)]
 pub fn part1(input: &FishCounts) -> i64 {
     fish_after_day(*input, 80)
@@ -31,3 +163,88 @@ pub fn part1(input: &FishCounts) -> i64 {
 pub fn part2(input: &FishCounts) -> i64 {
     fish_after_day(*input, 256)
 }
+
+#[aoc(day6, part2, Lifecycle)]
+pub fn part2_lifecycle(input: &FishCounts) -> u128 {
+    let mut fish = Population::new(LifecycleParams::PUZZLE, input);
+    *fish.time_series(256).last().unwrap()
+}
+
+#[aoc(day6, part2, Matrix)]
+pub fn part2_matrix(input: &FishCounts) -> u128 {
+    fish_after_day_jump(input, 256, Arithmetic::Checked).expect("too many fish")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    const EXAMPLE: &str = "3,4,3,1,2";
+
+    #[test]
+    fn example() {
+        let fish = parse_fish(EXAMPLE);
+        assert_eq!(part1(&fish), 5934);
+        assert_eq!(part2(&fish), 26984457539);
+        assert_eq!(part2_matrix(&fish), 26984457539);
+    }
+
+    #[test]
+    fn jump_ahead() {
+        const P: u64 = 1_000_000_007;
+        let modp = Arithmetic::Modulo(NonZeroU64::new(P).unwrap());
+        let fish = parse_fish(EXAMPLE);
+
+        for day in [0, 1, 18, 80, 256, 300] {
+            let exact = fish_after_day(fish, day) as u128;
+            assert_eq!(
+                fish_after_day_jump(&fish, day as u64, Arithmetic::Checked),
+                Some(exact)
+            );
+            assert_eq!(
+                fish_after_day_jump(&fish, day as u64, modp),
+                Some(exact % P as u128)
+            );
+        }
+
+        // Way too many fish to count exactly, but we can count them mod P.
+        assert_eq!(
+            fish_after_day_jump(&fish, 1_000_000_000_000, Arithmetic::Checked),
+            None
+        );
+        assert!(fish_after_day_jump(&fish, 1_000_000_000_000, modp).is_some());
+    }
+
+    #[test]
+    fn lifecycles() {
+        let fish = parse_fish(EXAMPLE);
+        assert_eq!(part2_lifecycle(&fish), 26984457539);
+
+        let series = Population::new(LifecycleParams::default(), &fish).time_series(18);
+        for (day, n) in series.iter().enumerate() {
+            assert_eq!(*n, fish_after_day(fish, day) as u128);
+        }
+        assert_eq!(series[..4], [5, 5, 6, 7]);
+
+        // Fish that reset to 1, are born at 1, and only live two days:
+        // each one spawns once just before it dies, so the school holds steady.
+        let params = LifecycleParams {
+            reset_timer: 1,
+            newborn_timer: 1,
+            death_age: Some(2),
+        };
+        let series = Population::new(params, &[1]).time_series(6);
+        assert_eq!(series, [1, 2, 1, 1, 1, 1, 1]);
+
+        // Immortal fish that spawn every day, once they're a day old,
+        // are Fibonacci's rabbits.
+        let params = LifecycleParams {
+            reset_timer: 0,
+            newborn_timer: 1,
+            death_age: None,
+        };
+        let series = Population::new(params, &[1]).time_series(8);
+        assert_eq!(series, [1, 2, 3, 5, 8, 13, 21, 34, 55]);
+    }
+}
@@ -4,21 +4,176 @@ fn crab_posits(input: &str) -> Vec<i16> {
     input.split(',').map(|n| n.parse().unwrap()).collect()
 }
 
-fn linear_fuel_cost(input: &[i16], to: i64) -> i64 {
-    input.iter().map(|c| (*c as i64 - to).abs()).sum()
+/// Moving a crab costs one fuel per step...
+pub fn linear(dist: i64) -> i64 {
+    dist
 }
 
-fn quadratic_fuel_cost(input: &[i16], to: i64) -> i64 {
-    input
+/// ...or one more each step than the last.
+pub fn triangular(dist: i64) -> i64 {
+    // (1..=distance).sum::<i64>()
+    (dist * (dist + 1)) / 2
+}
+
+fn fuel_cost<F: Fn(i64) -> i64>(input: &[i16], to: i64, cost: F) -> i64 {
+    input.iter().map(|c| cost((*c as i64 - to).abs())).sum()
+}
+
+pub fn linear_fuel_cost(input: &[i16], to: i64) -> i64 {
+    fuel_cost(input, to, linear)
+}
+
+pub fn quadratic_fuel_cost(input: &[i16], to: i64) -> i64 {
+    fuel_cost(input, to, triangular)
+}
+
+/// Where the crabs should line up, and what it costs them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    pub position: i64,
+    pub cost: i64,
+}
+
+/// Finds the cheapest position for all the crabs to line up on,
+/// given what it costs one crab to move a given distance.
+///
+/// As long as that cost never goes down and is convex (like `linear` and
+/// `triangular`), so is the total as a function of position, and we can
+/// binary search for where it stops going down.
+/// Ties go to the leftmost position.
+pub fn cheapest_alignment<F: Fn(i64) -> i64>(crabs: &[i16], cost: F) -> Alignment {
+    let lo = *crabs.iter().min().expect("no crabs") as i64;
+    let hi = *crabs.iter().max().unwrap() as i64;
+
+    minimize(lo, hi, |to| fuel_cost(crabs, to, &cost))
+}
+
+/// Binary searches [lo, hi] for the bottom of a convex total cost.
+fn minimize<T: Fn(i64) -> i64>(mut lo: i64, mut hi: i64, total: T) -> Alignment {
+    // Nobody gains by moving past the outermost crabs,
+    // so the best spot is somewhere in [lo, hi].
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if total(mid) <= total(mid + 1) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Alignment {
+        position: lo,
+        cost: total(lo),
+    }
+}
+
+/// Total fuel for a fleet where each crab counts `weights[i]` times,
+/// using one of the unweighted totals (like `linear_fuel_cost`) as a kernel
+pub fn weighted_fuel_cost<K: Fn(&[i16], i64) -> i64>(
+    crabs: &[i16],
+    weights: &[i64],
+    to: i64,
+    kernel: K,
+) -> i64 {
+    assert_eq!(crabs.len(), weights.len());
+    crabs
         .iter()
-        .map(|c| {
-            let dist = (*c as i64 - to).abs();
-            // (1..=distance).sum::<i64>()
-            (dist * (dist + 1)) / 2
-        })
+        .zip(weights)
+        .map(|(c, w)| w * kernel(std::slice::from_ref(c), to))
         .sum()
 }
 
+/// Like `cheapest_alignment`, but for a weighted fleet.
+/// Weights can't be negative, or the total isn't convex any more.
+pub fn cheapest_weighted_alignment<K: Fn(&[i16], i64) -> i64>(
+    crabs: &[i16],
+    weights: &[i64],
+    kernel: K,
+) -> Alignment {
+    assert!(weights.iter().all(|w| *w >= 0));
+    let lo = *crabs.iter().min().expect("no crabs") as i64;
+    let hi = *crabs.iter().max().unwrap() as i64;
+
+    minimize(lo, hi, |to| weighted_fuel_cost(crabs, weights, to, &kernel))
+}
+
+/// Splits a weighted fleet among (at most) `k` alignment points,
+/// minimizing the total fuel spent.
+///
+/// Each point gets a contiguous run of crabs once they're sorted by
+/// position, so this is a DP over prefixes of that order:
+/// the best way to line up the first `m` crabs on `j` points is the best
+/// way to line up some shorter prefix on `j - 1`, plus the rest on one more.
+///
+/// Returns each point with the fuel its crabs spend, from left to right.
+/// It's O(k * n^2) DP steps over n distinct positions, each costing a search,
+/// so it's meant for fleets in the hundreds, not thousands.
+pub fn multi_alignment<K: Fn(&[i16], i64) -> i64>(
+    crabs: &[i16],
+    weights: &[i64],
+    k: usize,
+    kernel: K,
+) -> Vec<Alignment> {
+    assert_eq!(crabs.len(), weights.len());
+    assert!(k > 0, "need somewhere to line up");
+
+    // Crabs on the same spot always go to the same point,
+    // so merge them into one heavier crab.
+    let mut by_position: Vec<(i16, i64)> =
+        crabs.iter().copied().zip(weights.iter().copied()).collect();
+    by_position.sort_unstable();
+    let mut positions: Vec<i16> = Vec::new();
+    let mut merged: Vec<i64> = Vec::new();
+    for (p, w) in by_position {
+        if positions.last() == Some(&p) {
+            *merged.last_mut().unwrap() += w;
+        } else {
+            positions.push(p);
+            merged.push(w);
+        }
+    }
+    let n = positions.len();
+    let k = std::cmp::min(k, n);
+
+    // group[i][m]: crabs i..m all lining up on one point
+    let mut group = vec![vec![None; n + 1]; n];
+    for i in 0..n {
+        for m in (i + 1)..=n {
+            group[i][m] = Some(cheapest_weighted_alignment(
+                &positions[i..m],
+                &merged[i..m],
+                &kernel,
+            ));
+        }
+    }
+
+    // best[j][m]: the cheapest way to line up the first m crabs on j points,
+    // and where the last point's run starts
+    let mut best: Vec<Vec<Option<(i64, usize)>>> = vec![vec![None; n + 1]; k + 1];
+    best[0][0] = Some((0, 0));
+    for j in 1..=k {
+        for m in j..=n {
+            best[j][m] = ((j - 1)..m)
+                .filter_map(|i| {
+                    let (before, _) = best[j - 1][i]?;
+                    Some((before + group[i][m].unwrap().cost, i))
+                })
+                .min();
+        }
+    }
+
+    // Walk back through the runs to find the points.
+    let mut points = Vec::with_capacity(k);
+    let mut m = n;
+    for j in (1..=k).rev() {
+        let (_, i) = best[j][m].unwrap();
+        points.push(group[i][m].unwrap());
+        m = i;
+    }
+    points.reverse();
+    points
+}
+
 fn median(input: &mut [i16]) -> i16 {
     let midway = input.len() / 2;
     let (_, median, _) = input.select_nth_unstable(midway);
@@ -32,10 +187,160 @@ pub fn part1(input: &str) -> i64 {
     linear_fuel_cost(&crabs, med)
 }
 
+#[aoc(day7, part1, Search)]
+pub fn part1_search(input: &str) -> i64 {
+    cheapest_alignment(&crab_posits(input), linear).cost
+}
+
+// The best spot is within half a step of the mean,
+// but which side it's on depends on the crabs, so search.
 #[aoc(day7, part2)]
 pub fn part2(input: &str) -> i64 {
+    cheapest_alignment(&crab_posits(input), triangular).cost
+}
+
+// Or skip the search: the best spot is within half a step of the mean,
+// so it's one of the integers around it.
+#[aoc(day7, part2, Mean)]
+pub fn part2_mean(input: &str) -> i64 {
     let crabs = crab_posits(input);
     let sum: i64 = crabs.iter().fold(0i64, |acc, c| acc + *c as i64);
     let mean = sum / crabs.len() as i64;
-    quadratic_fuel_cost(&crabs, mean)
+    ((mean - 1)..=(mean + 1))
+        .map(|to| quadratic_fuel_cost(&crabs, to))
+        .min()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "16,1,2,0,4,2,7,1,2,14";
+
+    #[test]
+    fn example() {
+        assert_eq!(part1(EXAMPLE), 37);
+        assert_eq!(part1_search(EXAMPLE), 37);
+        assert_eq!(part2(EXAMPLE), 168);
+        assert_eq!(part2_mean(EXAMPLE), 168);
+
+        let crabs = crab_posits(EXAMPLE);
+        assert_eq!(
+            cheapest_alignment(&crabs, linear),
+            Alignment {
+                position: 2,
+                cost: 37
+            }
+        );
+        assert_eq!(
+            cheapest_alignment(&crabs, triangular),
+            Alignment {
+                position: 5,
+                cost: 168
+            }
+        );
+    }
+
+    #[test]
+    fn not_the_mean() {
+        // The mean rounds down to 0, but 1 is cheaper.
+        let crabs = [0, 1, 1];
+        assert_eq!(quadratic_fuel_cost(&crabs, 0), 2);
+        assert_eq!(part2_mean("0,1,1"), 1);
+        assert_eq!(
+            cheapest_alignment(&crabs, triangular),
+            Alignment {
+                position: 1,
+                cost: 1
+            }
+        );
+
+        // Any other convex cost works too, like squared distance.
+        let crabs = [0, 0, 0, 10];
+        let squared = cheapest_alignment(&crabs, |d| d * d);
+        assert_eq!(squared.position, 2);
+        for to in 0..=10 {
+            assert!(fuel_cost(&crabs, to, |d| d * d) >= squared.cost);
+        }
+
+        assert_eq!(
+            cheapest_alignment(&[7], linear),
+            Alignment {
+                position: 7,
+                cost: 0
+            }
+        );
+    }
+
+    #[test]
+    fn weighted() {
+        let crabs = [0, 10];
+        assert_eq!(
+            cheapest_weighted_alignment(&crabs, &[1, 5], linear_fuel_cost),
+            Alignment {
+                position: 10,
+                cost: 10
+            }
+        );
+        // Three crabs at 0 and one at 10, so it's closer to 0
+        // but not all the way there.
+        let heavy = cheapest_weighted_alignment(&crabs, &[3, 1], quadratic_fuel_cost);
+        assert_eq!(heavy, cheapest_alignment(&[0, 0, 0, 10], triangular));
+        assert_eq!(heavy.position, 2);
+
+        // Weights of one are just the regular fleet.
+        let crabs = crab_posits(EXAMPLE);
+        let ones = vec![1; crabs.len()];
+        assert_eq!(
+            cheapest_weighted_alignment(&crabs, &ones, quadratic_fuel_cost),
+            cheapest_alignment(&crabs, triangular)
+        );
+    }
+
+    #[test]
+    fn multiple_targets() {
+        let crabs = [0, 1, 2, 100, 101, 102];
+        let ones = [1; 6];
+        assert_eq!(
+            multi_alignment(&crabs, &ones, 2, linear_fuel_cost),
+            [
+                Alignment {
+                    position: 1,
+                    cost: 2
+                },
+                Alignment {
+                    position: 101,
+                    cost: 2
+                }
+            ]
+        );
+        // More points than spots gives every spot its own.
+        let everyone = multi_alignment(&crabs, &ones, 10, linear_fuel_cost);
+        assert_eq!(everyone.len(), 6);
+        assert!(everyone.iter().all(|a| a.cost == 0));
+
+        // One point is the single alignment.
+        let crabs = crab_posits(EXAMPLE);
+        let ones = vec![1; crabs.len()];
+        assert_eq!(
+            multi_alignment(&crabs, &ones, 1, quadratic_fuel_cost),
+            [cheapest_alignment(&crabs, triangular)]
+        );
+
+        // Two points, checked against every way to split the sorted crabs
+        let mut sorted = crabs.clone();
+        sorted.sort_unstable();
+        let brute = (1..sorted.len())
+            .map(|split| {
+                let (left, right) = sorted.split_at(split);
+                cheapest_alignment(left, triangular).cost
+                    + cheapest_alignment(right, triangular).cost
+            })
+            .min()
+            .unwrap();
+        let two = multi_alignment(&crabs, &ones, 2, quadratic_fuel_cost);
+        assert_eq!(two.iter().map(|a| a.cost).sum::<i64>(), brute);
+        assert!(two[0].position < two[1].position);
+    }
 }
@@ -10,6 +10,60 @@ pub struct Heightmap {
     height: usize,
 }
 
+/// Which cells count as adjacent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// Up, down, left, and right, like the puzzle
+    #[default]
+    Four,
+    /// Diagonals too
+    Eight,
+}
+
+impl Neighborhood {
+    fn offsets(self) -> &'static [(isize, isize)] {
+        const FOUR: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        const EIGHT: [(isize, isize); 8] = [
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ];
+        match self {
+            Neighborhood::Four => &FOUR,
+            Neighborhood::Eight => &EIGHT,
+        }
+    }
+}
+
+/// Knobs for finding low points and basins.
+///
+/// `Default` gives the puzzle's rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasinOptions {
+    pub neighborhood: Neighborhood,
+    /// If set, a connected flat area counts as one low point
+    /// as long as everything around it is higher.
+    /// Otherwise, low points must be lower than all their neighbors.
+    pub plateaus: bool,
+    /// Cells this high or higher are walls between basins.
+    pub wall_height: i8,
+}
+
+impl Default for BasinOptions {
+    fn default() -> Self {
+        Self {
+            neighborhood: Neighborhood::Four,
+            plateaus: false,
+            wall_height: 9,
+        }
+    }
+}
+
 impl Heightmap {
     fn cell(&self, x: usize, y: usize) -> i8 {
         if x < self.width && y < self.height {
@@ -19,25 +73,78 @@ impl Heightmap {
         }
     }
 
-    fn risk_of_cell(&self, x: usize, y: usize) -> i8 {
-        let mut adjacents = [-1i8; 4];
+    /// In-bounds neighbors of (x, y)
+    fn neighbors(
+        &self,
+        x: usize,
+        y: usize,
+        neighborhood: Neighborhood,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        // Wrapping around zero puts us out of bounds, which we filter out.
+        neighborhood
+            .offsets()
+            .iter()
+            .map(move |(dx, dy)| (x.wrapping_add_signed(*dx), y.wrapping_add_signed(*dy)))
+            .filter(|(nx, ny)| *nx < self.width && *ny < self.height)
+    }
+
+    /// Finds every low point, one cell per low area if we're considering plateaus.
+    pub fn low_points(&self, options: &BasinOptions) -> Vec<(usize, usize)> {
+        let mut lows = Vec::new();
+
+        if !options.plateaus {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let center = self.cell(x, y);
+                    if self
+                        .neighbors(x, y, options.neighborhood)
+                        .all(|(nx, ny)| self.cell(nx, ny) > center)
+                    {
+                        lows.push((x, y));
+                    }
+                }
+            }
+            return lows;
+        }
 
-        adjacents[0] = self.cell(x - 1, y);
-        adjacents[1] = self.cell(x + 1, y);
-        adjacents[2] = self.cell(x, y - 1);
-        adjacents[3] = self.cell(x, y + 1);
+        // Flood each flat area, and see if anything around it is lower.
+        let mut visited = FixedBitSet::with_capacity(self.cells.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if visited.contains(x + y * self.width) {
+                    continue;
+                }
 
-        let center = self.cell(x, y);
+                let level = self.cell(x, y);
+                let mut is_low = true;
+                let mut to_visit = vec![(x, y)];
+                visited.insert(x + y * self.width);
 
-        if adjacents
-            .into_iter()
-            .filter(|a| *a >= 0)
-            .all(|a| a > center)
-        {
-            center + 1
-        } else {
-            0
+                while let Some((cx, cy)) = to_visit.pop() {
+                    for (nx, ny) in self.neighbors(cx, cy, options.neighborhood) {
+                        let n = self.cell(nx, ny);
+                        if n < level {
+                            is_low = false;
+                        } else if n == level && !visited.put(nx + ny * self.width) {
+                            to_visit.push((nx, ny));
+                        }
+                    }
+                }
+
+                if is_low {
+                    lows.push((x, y));
+                }
+            }
         }
+        lows
+    }
+
+    /// The sum of each low point's height plus one
+    pub fn risk_level(&self, options: &BasinOptions) -> i64 {
+        self.low_points(options)
+            .into_iter()
+            .map(|(x, y)| self.cell(x, y) as i64 + 1)
+            .sum()
     }
 }
 
@@ -79,50 +186,430 @@ pub fn parse_heightmap(input: &str) -> Heightmap {
 #[aoc(day9, part1)]
 pub fn part1(input: &Heightmap) -> i64 {
     // println!("{:?}", input);
-    let mut risk: i64 = 0;
+    input.risk_level(&BasinOptions::default())
+}
+
+pub fn basin_size(input: &Heightmap, visited: &mut FixedBitSet, x: usize, y: usize) -> i64 {
+    let mut size = 0;
+    flood_fill(input, visited, x, y, Neighborhood::Four, |_| size += 1);
+    size
+}
+
+/// Visits every unvisited cell connected to (x, y),
+/// marking them as it goes.
+///
+/// Uses an explicit stack instead of recursion so that huge basins
+/// don't overflow the real one.
+fn flood_fill<F: FnMut(usize)>(
+    input: &Heightmap,
+    visited: &mut FixedBitSet,
+    x: usize,
+    y: usize,
+    neighborhood: Neighborhood,
+    mut on_cell: F,
+) {
+    let mut to_visit = vec![(x, y)];
+
+    while let Some((x, y)) = to_visit.pop() {
+        if x >= input.width || y >= input.height {
+            continue;
+        }
+        let idx = x + y * input.width;
+        if visited.contains(idx) {
+            continue;
+        }
 
-    for y in 0..input.height {
-        for x in 0..input.width {
-            risk += input.risk_of_cell(x, y) as i64;
+        visited.insert(idx);
+        on_cell(idx);
+
+        // Wrapping around zero puts us out of bounds, which we check above.
+        for (dx, dy) in neighborhood.offsets() {
+            to_visit.push((x.wrapping_add_signed(*dx), y.wrapping_add_signed(*dy)));
         }
     }
-    risk
 }
 
-pub fn basin_size(input: &Heightmap, visited: &mut FixedBitSet, x: usize, y: usize) -> i64 {
-    let idx = x + y * input.width;
-    if x >= input.width || y >= input.height || visited.contains(idx) {
-        return 0;
+/// Marks all wall cells as visited, since they're not part of any basin.
+fn walls(input: &Heightmap, wall_height: i8) -> FixedBitSet {
+    let mut visited = FixedBitSet::with_capacity(input.cells.len());
+
+    for (i, _cell) in input
+        .cells
+        .iter()
+        .enumerate()
+        .filter(|(_i, c)| **c >= wall_height)
+    {
+        visited.insert(i);
+    }
+    visited
+}
+
+/// Every basin in a heightmap, and which cells belong to each
+pub struct Basins {
+    /// The basin each cell belongs to, or `None` for walls
+    labels: Vec<Option<usize>>,
+    width: usize,
+    sizes: Vec<usize>,
+    low_points: Vec<(usize, usize)>,
+}
+
+impl Basins {
+    pub fn new(input: &Heightmap) -> Self {
+        Self::with_options(input, &BasinOptions::default())
+    }
+
+    /// Labels basins using the given neighborhood and wall height.
+    /// (Plateaus don't matter here - they're in whatever basin surrounds them.)
+    pub fn with_options(input: &Heightmap, options: &BasinOptions) -> Self {
+        let mut visited = walls(input, options.wall_height);
+        let mut labels = vec![None; input.cells.len()];
+        let mut sizes = Vec::new();
+        let mut low_points = Vec::new();
+
+        for y in 0..input.height {
+            for x in 0..input.width {
+                if visited.contains(x + y * input.width) {
+                    continue;
+                }
+
+                let id = sizes.len();
+                let mut size = 0;
+                let mut lowest = x + y * input.width;
+
+                flood_fill(input, &mut visited, x, y, options.neighborhood, |idx| {
+                    labels[idx] = Some(id);
+                    size += 1;
+                    if input.cells[idx] < input.cells[lowest] {
+                        lowest = idx;
+                    }
+                });
+
+                sizes.push(size);
+                low_points.push((lowest % input.width, lowest / input.width));
+            }
+        }
+
+        Self {
+            labels,
+            width: input.width,
+            sizes,
+            low_points,
+        }
     }
 
-    visited.insert(idx);
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// The basin containing (x, y), or `None` if it's a wall
+    pub fn basin_at(&self, x: usize, y: usize) -> Option<usize> {
+        assert!(x < self.width);
+        self.labels[x + y * self.width]
+    }
+
+    pub fn size(&self, basin: usize) -> usize {
+        self.sizes[basin]
+    }
 
-    1 + basin_size(input, visited, x - 1, y)
-        + basin_size(input, visited, x + 1, y)
-        + basin_size(input, visited, x, y - 1)
-        + basin_size(input, visited, x, y + 1)
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    pub fn low_point(&self, basin: usize) -> (usize, usize) {
+        self.low_points[basin]
+    }
+}
+
+/// Draws each basin with a letter (repeating after z),
+/// and walls as `#`.
+impl Debug for Basins {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        for line in self.labels.chunks(self.width) {
+            for label in line {
+                match label {
+                    Some(id) => write!(f, "{}", (b'a' + (id % 26) as u8) as char)?,
+                    None => write!(f, "#")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
 #[aoc(day9, part2)]
 pub fn part2(input: &Heightmap) -> i64 {
-    let mut visited = FixedBitSet::with_capacity(input.cells.len());
+    let basins = Basins::new(input);
 
-    // Mark all max-height cells as visited.
-    for (i, _cell) in input.cells.iter().enumerate().filter(|(_i, c)| **c == 9) {
-        visited.insert(i);
+    let mut sizes = basins.sizes().to_vec();
+    sizes.sort_unstable();
+    sizes.iter().rev().take(3).product::<usize>() as i64
+}
+
+/// A disjoint set of basin labels, tracking each set's size at its root
+struct UnionFind {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl UnionFind {
+    fn with_sizes(sizes: Vec<usize>) -> Self {
+        let parents = (0..sizes.len()).collect();
+        Self { parents, sizes }
+    }
+
+    fn add(&mut self) -> usize {
+        let id = self.parents.len();
+        self.parents.push(id);
+        self.sizes.push(0);
+        id
     }
 
-    let mut basins = Vec::new();
+    fn find(&mut self, mut id: usize) -> usize {
+        while self.parents[id] != id {
+            // Path halving
+            self.parents[id] = self.parents[self.parents[id]];
+            id = self.parents[id];
+        }
+        id
+    }
 
-    for y in 0..input.height {
-        for x in 0..input.width {
-            let bs = basin_size(input, &mut visited, x, y);
-            if bs > 0 {
-                basins.push(bs);
+    fn union(&mut self, a: usize, b: usize) -> usize {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return a;
+        }
+        let (big, small) = if self.sizes[a] >= self.sizes[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parents[small] = big;
+        self.sizes[big] += self.sizes[small];
+        big
+    }
+}
+
+/// Finds the same basin sizes as `basin_size()` (in no particular order),
+/// but reads the heightmap one row at a time and only remembers
+/// the labels for the previous row.
+///
+/// Each row starts a fresh union-find holding only the basins that
+/// touched the previous row. Any basin that doesn't reach the current row
+/// is finished, so we report its size and forget it.
+///
+/// Walls and adjacency come from `options`, like `Basins::with_options()`.
+pub fn streaming_basin_sizes<I, R>(rows: I, options: &BasinOptions) -> Vec<usize>
+where
+    I: IntoIterator<Item = R>,
+    R: AsRef<[i8]>,
+{
+    let mut finished = Vec::new();
+
+    // Labels for the previous row, indexing into `carried`
+    let mut prev: Vec<Option<usize>> = Vec::new();
+    let mut carried: Vec<usize> = Vec::new();
+
+    for row in rows {
+        let row = row.as_ref();
+        let num_carried = carried.len();
+        let mut sets = UnionFind::with_sizes(std::mem::take(&mut carried));
+        let mut current: Vec<Option<usize>> = Vec::with_capacity(row.len());
+
+        for (x, height) in row.iter().enumerate() {
+            if *height >= options.wall_height {
+                current.push(None);
+                continue;
             }
+
+            // Everything we've already labeled that touches this cell:
+            // to the left and above, plus the upper corners with diagonals.
+            let left = x.checked_sub(1).and_then(|l| current[l]);
+            let up = prev.get(x).copied().flatten();
+            let corners = match options.neighborhood {
+                Neighborhood::Four => [None, None],
+                Neighborhood::Eight => [
+                    x.checked_sub(1)
+                        .and_then(|l| prev.get(l).copied().flatten()),
+                    prev.get(x + 1).copied().flatten(),
+                ],
+            };
+            let label = [left, up]
+                .into_iter()
+                .chain(corners)
+                .flatten()
+                .reduce(|a, b| sets.union(a, b))
+                .unwrap_or_else(|| sets.add());
+            let root = sets.find(label);
+            sets.sizes[root] += 1;
+            current.push(Some(root));
         }
+
+        // Renumber the basins that reach this row, starting from zero.
+        let mut renumbered: Vec<Option<usize>> = vec![None; sets.parents.len()];
+        for label in current.iter_mut().flatten() {
+            let root = sets.find(*label);
+            *label = *renumbered[root].get_or_insert_with(|| {
+                carried.push(sets.sizes[root]);
+                carried.len() - 1
+            });
+        }
+
+        // Anything from the last row that didn't make it to this one is done.
+        for (id, renumber) in renumbered.iter().enumerate().take(num_carried) {
+            if renumber.is_none() && sets.parents[id] == id {
+                finished.push(sets.sizes[id]);
+            }
+        }
+
+        prev = current;
     }
 
-    basins.sort_unstable();
-    basins.iter().rev().take(3).product()
+    finished.extend(carried);
+    finished
+}
+
+#[aoc(day9, part2, UnionFind)]
+pub fn part2_union_find(input: &Heightmap) -> i64 {
+    let options = BasinOptions::default();
+    let mut sizes = streaming_basin_sizes(input.cells.chunks(input.width), &options);
+    sizes.sort_unstable();
+    sizes.iter().rev().take(3).product::<usize>() as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+2199943210
+3987894921
+9856789892
+8767896789
+9899965678";
+
+    #[test]
+    fn example() {
+        let map = parse_heightmap(EXAMPLE);
+        assert_eq!(part1(&map), 15);
+        assert_eq!(part2(&map), 1134);
+    }
+
+    #[test]
+    fn labels() {
+        let basins = Basins::new(&parse_heightmap(EXAMPLE));
+        assert_eq!(basins.len(), 4);
+        assert_eq!(basins.sizes(), [3, 9, 14, 9]);
+        assert_eq!(basins.low_point(0), (1, 0));
+        assert_eq!(basins.low_point(1), (9, 0));
+        assert_eq!(basins.low_point(2), (2, 2));
+        assert_eq!(basins.low_point(3), (6, 4));
+
+        assert_eq!(basins.basin_at(0, 0), Some(0));
+        assert_eq!(basins.basin_at(2, 0), None);
+        assert_eq!(basins.basin_at(9, 4), Some(3));
+        assert_eq!(
+            format!("{:?}", basins),
+            "\
+aa###bbbbb
+a#ccc#b#bb
+#ccccc#d#b
+ccccc#ddd#
+#c###ddddd
+"
+        );
+    }
+
+    #[test]
+    fn union_find_matches_flood_fill() {
+        let map = parse_heightmap(EXAMPLE);
+        let default = BasinOptions::default();
+        let mut sizes = streaming_basin_sizes(map.cells.chunks(map.width), &default);
+        sizes.sort_unstable();
+        assert_eq!(sizes, [3, 9, 9, 14]);
+        assert_eq!(part2_union_find(&map), 1134);
+
+        // Something with more interesting shapes:
+        // U-turns that only join at the bottom, spirals, and so on.
+        let tricky = parse_heightmap(
+            "\
+0909000
+0909090
+0009090
+9999090
+0000090
+0999990
+0000000
+9099909
+0090009",
+        );
+
+        // Diagonals that only touch at their corners, both ways
+        let diagonals = parse_heightmap("1919\n9191\n1991\n9119");
+        let ridge = parse_heightmap("151\n252");
+
+        for map in [&map, &tricky, &diagonals, &ridge] {
+            for neighborhood in [Neighborhood::Four, Neighborhood::Eight] {
+                for wall_height in [9, 5, 2] {
+                    let options = BasinOptions {
+                        neighborhood,
+                        wall_height,
+                        ..default
+                    };
+                    let basins = Basins::with_options(map, &options);
+                    let mut flood_sizes = basins.sizes().to_vec();
+                    let mut stream_sizes =
+                        streaming_basin_sizes(map.cells.chunks(map.width), &options);
+                    flood_sizes.sort_unstable();
+                    stream_sizes.sort_unstable();
+                    assert_eq!(stream_sizes, flood_sizes, "{:?}\n{:?}", options, basins);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn options() {
+        let flat = parse_heightmap("9999\n9119\n9999");
+        let mut options = BasinOptions::default();
+        assert_eq!(flat.low_points(&options), []);
+        options.plateaus = true;
+        assert_eq!(flat.low_points(&options), [(1, 1)]);
+        assert_eq!(flat.risk_level(&options), 2);
+
+        let diagonal = parse_heightmap("19\n91");
+        let mut options = BasinOptions::default();
+        assert_eq!(diagonal.low_points(&options), [(0, 0), (1, 1)]);
+        assert_eq!(Basins::with_options(&diagonal, &options).sizes(), [1, 1]);
+        options.neighborhood = Neighborhood::Eight;
+        assert_eq!(diagonal.low_points(&options), []);
+        assert_eq!(Basins::with_options(&diagonal, &options).sizes(), [2]);
+        options.plateaus = true;
+        assert_eq!(diagonal.low_points(&options), [(0, 0)]);
+
+        let ridge = parse_heightmap("151\n252");
+        let mut options = BasinOptions::default();
+        assert_eq!(Basins::with_options(&ridge, &options).sizes(), [6]);
+        options.wall_height = 5;
+        let basins = Basins::with_options(&ridge, &options);
+        assert_eq!(basins.sizes(), [2, 2]);
+        assert_eq!(basins.low_point(1), (2, 0));
+    }
+
+    #[test]
+    fn huge_basin() {
+        // Way more cells than we could recurse through
+        let size = 1000;
+        let map = Heightmap {
+            cells: vec![0; size * size],
+            width: size,
+            height: size,
+        };
+        let mut visited = FixedBitSet::with_capacity(size * size);
+        assert_eq!(basin_size(&map, &mut visited, 0, 0), (size * size) as i64);
+    }
 }
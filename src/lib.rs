@@ -1,5 +1,8 @@
 use aoc_runner_derive::aoc_lib;
 
+pub mod automaton;
+pub mod matrix;
+
 pub mod day1;
 pub mod day10;
 pub mod day11;
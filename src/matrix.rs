@@ -0,0 +1,207 @@
+//! Small square integer matrices, for days whose state is a vector of counts
+//! that some fixed linear map updates every step.
+//! Raising the map to the nth power by repeated squaring jumps n steps ahead
+//! in O(log n) multiplications.
+
+use std::num::NonZeroU64;
+
+/// How to keep numbers from getting out of hand
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Exact answers, or nothing if they don't fit in a u128
+    Checked,
+    /// Everything modulo this (ideally prime) number.
+    /// It's a u64 so products of two residues always fit in a u128,
+    /// and nonzero since nothing is left over after dividing by zero.
+    Modulo(NonZeroU64),
+}
+
+impl Arithmetic {
+    pub fn reduce(self, a: u128) -> u128 {
+        match self {
+            Arithmetic::Checked => a,
+            Arithmetic::Modulo(m) => a % m.get() as u128,
+        }
+    }
+
+    pub fn add(self, a: u128, b: u128) -> Option<u128> {
+        match self {
+            Arithmetic::Checked => a.checked_add(b),
+            // Both are already reduced, so this can't overflow.
+            Arithmetic::Modulo(_) => Some(self.reduce(a + b)),
+        }
+    }
+
+    pub fn mul(self, a: u128, b: u128) -> Option<u128> {
+        match self {
+            Arithmetic::Checked => a.checked_mul(b),
+            Arithmetic::Modulo(_) => Some(self.reduce(a * b)),
+        }
+    }
+
+    /// Sums everything, or `None` if it overflows.
+    pub fn sum<I: IntoIterator<Item = u128>>(self, vals: I) -> Option<u128> {
+        vals.into_iter()
+            .try_fold(0, |acc, v| self.add(acc, self.reduce(v)))
+    }
+}
+
+/// A square matrix, stored row by row.
+///
+/// It maps a column vector of "before" counts to "after" counts,
+/// so `m[(to, from)]` is how many of `to` each `from` becomes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matrix {
+    size: usize,
+    cells: Vec<u128>,
+}
+
+impl Matrix {
+    pub fn zero(size: usize) -> Self {
+        Self {
+            size,
+            cells: vec![0; size * size],
+        }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        let mut m = Self::zero(size);
+        for i in 0..size {
+            m[(i, i)] = 1;
+        }
+        m
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn mul(&self, other: &Self, arith: Arithmetic) -> Option<Self> {
+        assert_eq!(self.size, other.size);
+        let n = self.size;
+
+        let mut product = Self::zero(n);
+        for r in 0..n {
+            for k in 0..n {
+                let a = self[(r, k)];
+                // Transition matrices are mostly zeros.
+                if a == 0 {
+                    continue;
+                }
+                for c in 0..n {
+                    let b = other[(k, c)];
+                    if b == 0 {
+                        continue;
+                    }
+                    let cell = &mut product.cells[r * n + c];
+                    *cell = arith.add(*cell, arith.mul(a, b)?)?;
+                }
+            }
+        }
+        Some(product)
+    }
+
+    /// Raises the matrix to the given power by repeated squaring.
+    pub fn pow(&self, mut exp: u64, arith: Arithmetic) -> Option<Self> {
+        let mut result = Self::identity(self.size);
+        let mut base = self.reduced(arith);
+
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base, arith)?;
+            }
+            exp >>= 1;
+            // Don't square once more than we need to - it might overflow.
+            if exp > 0 {
+                base = base.mul(&base, arith)?;
+            }
+        }
+        Some(result)
+    }
+
+    /// Multiplies the matrix by the given column vector.
+    pub fn apply(&self, v: &[u128], arith: Arithmetic) -> Option<Vec<u128>> {
+        assert_eq!(v.len(), self.size);
+
+        self.cells
+            .chunks(self.size)
+            .map(|row| {
+                row.iter().zip(v).try_fold(0, |acc, (a, b)| {
+                    arith.add(acc, arith.mul(*a, arith.reduce(*b))?)
+                })
+            })
+            .collect()
+    }
+
+    fn reduced(&self, arith: Arithmetic) -> Self {
+        Self {
+            size: self.size,
+            cells: self.cells.iter().map(|c| arith.reduce(*c)).collect(),
+        }
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for Matrix {
+    type Output = u128;
+
+    fn index(&self, (r, c): (usize, usize)) -> &u128 {
+        &self.cells[r * self.size + c]
+    }
+}
+
+impl std::ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut u128 {
+        &mut self.cells[r * self.size + c]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // [F(n+1), F(n)] from [F(n), F(n-1)]
+    fn fibonacci() -> Matrix {
+        let mut m = Matrix::zero(2);
+        m[(0, 0)] = 1;
+        m[(0, 1)] = 1;
+        m[(1, 0)] = 1;
+        m
+    }
+
+    #[test]
+    fn fibonacci_numbers() {
+        let fib = fibonacci();
+        let f = |n| fib.pow(n, Arithmetic::Checked).map(|m| m[(1, 0)]);
+
+        assert_eq!(f(0), Some(0));
+        assert_eq!(f(1), Some(1));
+        assert_eq!(f(10), Some(55));
+        assert_eq!(f(90), Some(2880067194370816120));
+        // F(186) is the last one that fits in a u128,
+        // and M^n also holds F(n + 1).
+        assert!(f(185).is_some());
+        assert_eq!(f(186), None);
+
+        assert_eq!(fib.apply(&[1, 0], Arithmetic::Checked), Some(vec![1, 1]));
+    }
+
+    #[test]
+    fn modular() {
+        const P: u64 = 1_000_000_007;
+        let fib = fibonacci();
+        let modp = Arithmetic::Modulo(NonZeroU64::new(P).unwrap());
+
+        let exact = fib.pow(150, Arithmetic::Checked).unwrap();
+        assert_eq!(fib.pow(150, modp).unwrap(), exact.reduced(modp));
+
+        // M^(a + b) = M^a * M^b, even way past where exact numbers fit
+        let a = 1_000_000_000_000;
+        let b = 123_456_789;
+        let split = fib
+            .pow(a, modp)
+            .unwrap()
+            .mul(&fib.pow(b, modp).unwrap(), modp)
+            .unwrap();
+        assert_eq!(fib.pow(a + b, modp).unwrap(), split);
+    }
+}